@@ -0,0 +1,245 @@
+use crate::{
+    calendar::{CalendarBackend, OutOfOffice},
+    error::AutospielerError,
+    local_time, read_optional_env, read_parsed_env,
+};
+use chrono::{Days, NaiveDate, NaiveDateTime};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Reason given in Spielerplus for absences without one.
+const DEFAULT_REASON: &str = "Abwesend";
+
+/// Days the attendee is away, from `from` until `until`, both included.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Absence {
+    pub from: NaiveDate,
+    pub until: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Absence {
+    fn reason(&self) -> &str {
+        self.reason.as_deref().unwrap_or(DEFAULT_REASON)
+    }
+}
+
+impl std::fmt::Display for Absence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}: {}", self.from, self.until, self.reason())
+    }
+}
+
+pub struct AbsenceConfig {
+    /// JSON file with the absences, from `ABSENCES_FILE`.
+    pub path: PathBuf,
+    /// Whether scheduled automatic replies in the calendar count as an absence.
+    pub from_out_of_office: bool,
+    /// Days automatic replies without an end count as an absence, today included.
+    pub out_of_office_days: u64,
+}
+
+impl AbsenceConfig {
+    pub fn from_env(state_dir: &Path) -> Result<Self, AutospielerError> {
+        Ok(AbsenceConfig {
+            path: read_optional_env("ABSENCES_FILE")?
+                .map(PathBuf::from)
+                .unwrap_or_else(|| state_dir.join("absences.json")),
+            from_out_of_office: read_parsed_env("ABSENCES_FROM_OUT_OF_OFFICE", false)?,
+            out_of_office_days: read_parsed_env("ABSENCES_OUT_OF_OFFICE_DAYS", 1)?,
+        })
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<Absence>, AutospielerError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| {
+        AutospielerError::config(format!(
+            "Could not parse ABSENCES_FILE {}: {e}",
+            path.display()
+        ))
+    })
+}
+
+fn save(path: &Path, absences: &[Absence]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(absences)?)
+}
+
+/// Everything that makes the attendee absent during a sync.
+pub struct Absences {
+    absences: Vec<Absence>,
+    out_of_office: Option<OutOfOffice>,
+}
+
+impl Absences {
+    /// Reads the absences anew, so changes apply without a restart.
+    pub fn load(
+        config: &AbsenceConfig,
+        calendar: &mut dyn CalendarBackend,
+    ) -> Result<Self, AutospielerError> {
+        let out_of_office = if config.from_out_of_office {
+            calendar.out_of_office().unwrap_or_else(|err| {
+                eprintln!("could not read the automatic replies: {err}");
+                None
+            })
+        } else {
+            None
+        };
+        let today = local_time::local_now().date();
+        let out_of_office = out_of_office
+            .map(|out_of_office| with_end(out_of_office, today, config.out_of_office_days));
+
+        Ok(Absences::new(load(&config.path)?, out_of_office))
+    }
+
+    pub fn new(absences: Vec<Absence>, out_of_office: Option<OutOfOffice>) -> Self {
+        Absences {
+            absences,
+            out_of_office,
+        }
+    }
+
+    /// Why the attendee is absent at `start`, `None` if they aren't.
+    pub fn reason_at(&self, start: NaiveDateTime) -> Option<&str> {
+        if let Some(absence) = self
+            .absences
+            .iter()
+            .find(|absence| absence.from <= start.date() && start.date() <= absence.until)
+        {
+            return Some(absence.reason());
+        }
+        self.out_of_office
+            .as_ref()
+            .filter(|out_of_office| out_of_office.contains(start))
+            .map(|_| DEFAULT_REASON)
+    }
+}
+
+/// Ends automatic replies that are on until further notice after `days` days from
+/// `today`, as nobody can tell if they'll still be on for events further ahead.
+fn with_end(out_of_office: OutOfOffice, today: NaiveDate, days: u64) -> OutOfOffice {
+    let end = out_of_office.end.or_else(|| {
+        let last_day = today.checked_add_days(Days::new(days))?;
+        last_day.and_hms_opt(0, 0, 0)
+    });
+    OutOfOffice {
+        end,
+        ..out_of_office
+    }
+}
+
+fn parse_date(value: Option<&String>) -> Result<NaiveDate, AutospielerError> {
+    let value = value.ok_or_else(|| AutospielerError::config(USAGE))?;
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| AutospielerError::config(format!("invalid date '{value}': {e}")))
+}
+
+const USAGE: &str = "usage: absence list | absence add FROM UNTIL [REASON] | absence remove FROM UNTIL, with dates like 2024-07-15";
+
+/// The `absence` command, which edits the absences in the file of `config`.
+pub fn command(config: &AbsenceConfig, args: &[String]) -> Result<(), AutospielerError> {
+    let mut absences = load(&config.path)?;
+
+    match args.first().map(String::as_str) {
+        Some("list") => {
+            for absence in &absences {
+                println!("{absence}");
+            }
+        }
+        Some("add") => {
+            let absence = Absence {
+                from: parse_date(args.get(1))?,
+                until: parse_date(args.get(2))?,
+                reason: Some(args[3..].join(" ")).filter(|reason| !reason.is_empty()),
+            };
+            if absence.until < absence.from {
+                return Err(AutospielerError::config(format!(
+                    "{} is before {}",
+                    absence.until, absence.from
+                )));
+            }
+            println!("Added {absence}");
+            absences.push(absence);
+            save(&config.path, &absences)?;
+        }
+        Some("remove") => {
+            let from = parse_date(args.get(1))?;
+            let until = parse_date(args.get(2))?;
+            let count = absences.len();
+            absences.retain(|absence| absence.from != from || absence.until != until);
+            if absences.len() == count {
+                return Err(AutospielerError::config(format!(
+                    "no absence from {from} until {until}"
+                )));
+            }
+            println!("Removed the absence from {from} until {until}");
+            save(&config.path, &absences)?;
+        }
+        _ => return Err(AutospielerError::config(USAGE)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn counts_open_ended_automatic_replies_for_the_next_days() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 11).unwrap();
+        let always_on = OutOfOffice {
+            start: None,
+            end: None,
+        };
+
+        let absences = Absences::new(Vec::new(), Some(with_end(always_on, today, 1)));
+        assert_eq!(
+            absences.reason_at(at("2024-03-11 19:30")),
+            Some(DEFAULT_REASON)
+        );
+        assert_eq!(absences.reason_at(at("2024-03-12 00:00")), None);
+
+        let always_on = OutOfOffice {
+            start: None,
+            end: None,
+        };
+        let absences = Absences::new(Vec::new(), Some(with_end(always_on, today, 3)));
+        assert_eq!(
+            absences.reason_at(at("2024-03-13 23:00")),
+            Some(DEFAULT_REASON)
+        );
+        assert_eq!(absences.reason_at(at("2024-03-14 19:30")), None);
+    }
+
+    #[test]
+    fn keeps_the_end_of_scheduled_automatic_replies() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 11).unwrap();
+        let scheduled = OutOfOffice {
+            start: Some(at("2024-03-11 08:00")),
+            end: Some(at("2024-03-20 18:00")),
+        };
+
+        let absences = Absences::new(Vec::new(), Some(with_end(scheduled, today, 1)));
+        assert_eq!(absences.reason_at(at("2024-03-11 07:00")), None);
+        assert_eq!(
+            absences.reason_at(at("2024-03-19 19:30")),
+            Some(DEFAULT_REASON)
+        );
+        assert_eq!(absences.reason_at(at("2024-03-20 19:30")), None);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    absences::Absences,
+    calendar::{Attendance, BusyTime},
+    conflicts::ConflictAnswers,
+    deadlines::DeadlineConfig,
+    filters::FilteredEvent,
+    rules::DauerzusageRules,
+    spielerplus::Team,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// A Spielerplus event as far as answering it is concerned.
+pub struct AnsweredEvent<'a> {
+    /// Profile id the event is answered for.
+    pub profile_id: &'a str,
+    pub team: Option<&'a Team>,
+    pub event: &'a FilteredEvent<'a>,
+    pub end: NaiveDateTime,
+    pub deadline: Option<NaiveDateTime>,
+}
+
+/// What the attendance store knows about the event.
+pub struct AnswerState {
+    /// The answer the synced calendar and Spielerplus answers resolve to.
+    pub resolved: Option<Attendance>,
+    pub declined_for_absence: bool,
+    /// The answer before the event was declined for an absence.
+    pub answer_before_absence: Option<Attendance>,
+}
+
+/// Everything that can answer an event besides the attendee.
+pub struct Answerers<'a> {
+    pub absences: &'a Absences,
+    pub conflict_answers: &'a ConflictAnswers,
+    pub rules: &'a DauerzusageRules,
+    pub deadlines: &'a DeadlineConfig,
+}
+
+/// Where an answer comes from.
+#[derive(Debug, PartialEq)]
+pub enum AnswerSource {
+    /// The answer of the attendee, in the calendar or in Spielerplus.
+    Synced,
+    Absence,
+    /// The event was declined for an absence that doesn't cover it anymore.
+    AbsenceEnded,
+    /// A conflicting appointment, with its subject if the calendar tells.
+    Conflict(Option<String>),
+    Rule,
+    /// The participation deadline is close.
+    Deadline(NaiveDateTime),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Answer<'a> {
+    pub attendance: Attendance,
+    pub source: AnswerSource,
+    /// Reason given in Spielerplus.
+    pub reason: &'a str,
+}
+
+/// The answer Spielerplus should have. Events during an absence are declined once, the
+/// answer of the attendee comes next. If nobody answered yet, a conflicting appointment,
+/// the Dauerzusage rules or the default answer before the deadline may answer, in that
+/// order. `busy_times` is only asked if a conflict could answer the event.
+///
+/// If an absence no longer covers an event it declined and nobody answered since, the
+/// event gets its answer from before the absence back. Without one it's answered like an
+/// unanswered event, and `Unsure` if nothing answers it.
+pub fn resolve_answer<'a>(
+    event: &AnsweredEvent,
+    state: AnswerState,
+    answerers: &Answerers<'a>,
+    busy_times: impl FnOnce() -> Option<Vec<BusyTime>>,
+    today: NaiveDate,
+    now: NaiveDateTime,
+) -> Option<Answer<'a>> {
+    let answer = |attendance, source, reason| {
+        Some(Answer {
+            attendance,
+            source,
+            reason,
+        })
+    };
+    let start = event.event.start;
+
+    let absence = answerers.absences.reason_at(start);
+    if let Some(reason) = absence.filter(|_| !state.declined_for_absence) {
+        return answer(Attendance::Declined, AnswerSource::Absence, reason);
+    }
+    let absence_ended = state.declined_for_absence
+        && absence.is_none()
+        && state.resolved == Some(Attendance::Declined);
+    if absence_ended {
+        if let Some(before) = state.answer_before_absence {
+            return answer(before, AnswerSource::AbsenceEnded, "-");
+        }
+    } else if let Some(resolved) = state.resolved {
+        return answer(resolved, AnswerSource::Synced, "-");
+    }
+
+    if let Some(attendance) = answerers.conflict_answers.answer(event.event.kind) {
+        let conflict = busy_times()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|busy| busy.overlaps(start, event.end));
+        if let Some(conflict) = conflict {
+            return answer(
+                attendance,
+                AnswerSource::Conflict(conflict.subject),
+                &answerers.conflict_answers.reason,
+            );
+        }
+    }
+    if let Some(attendance) =
+        answerers
+            .rules
+            .answer(event.profile_id, event.team, event.event, today)
+    {
+        return answer(attendance, AnswerSource::Rule, "-");
+    }
+    if let Some(deadline) = event.deadline {
+        if let Some(attendance) = answerers.deadlines.default_answer_due(deadline, now) {
+            return answer(attendance, AnswerSource::Deadline(deadline), "-");
+        }
+    }
+    if absence_ended {
+        return answer(Attendance::Unsure, AnswerSource::AbsenceEnded, "-");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{absences::Absence, filters::EventKind};
+    use chrono::Duration;
+    use Attendance::{Accepted, Declined, Unsure};
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// Training on 2024-03-12 19:30-21:00 with its deadline at 18:00, the day before.
+    struct Setup {
+        absences: Absences,
+        conflict_answers: ConflictAnswers,
+        rules: DauerzusageRules,
+        deadlines: DeadlineConfig,
+        busy_times: Vec<BusyTime>,
+        now: NaiveDateTime,
+    }
+
+    impl Setup {
+        /// Every source has an answer.
+        fn new() -> Self {
+            Setup {
+                absences: Absences::new(
+                    vec![Absence {
+                        from: NaiveDate::from_ymd_opt(2024, 3, 11).unwrap(),
+                        until: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+                        reason: Some("Ferien".to_owned()),
+                    }],
+                    None,
+                ),
+                conflict_answers: ConflictAnswers::parse("training=unsure", "Konflikt".to_owned())
+                    .unwrap(),
+                rules: serde_json::from_str(
+                    r#"[{"when": {"kinds": ["training"]}, "answer": "accepted"}]"#,
+                )
+                .unwrap(),
+                deadlines: DeadlineConfig {
+                    default_answer: Some(Declined),
+                    default_answer_before: Duration::hours(2),
+                    reminders: None,
+                },
+                busy_times: vec![BusyTime {
+                    start: at("2024-03-12 19:00"),
+                    end: at("2024-03-12 20:00"),
+                    subject: Some("Elternabend".to_owned()),
+                }],
+                now: at("2024-03-11 17:00"),
+            }
+        }
+
+        fn answer(
+            &self,
+            resolved: Option<Attendance>,
+            declined_for_absence: bool,
+        ) -> Option<Answer<'_>> {
+            self.answer_after_absence(resolved, declined_for_absence, None)
+        }
+
+        fn answer_after_absence(
+            &self,
+            resolved: Option<Attendance>,
+            declined_for_absence: bool,
+            answer_before_absence: Option<Attendance>,
+        ) -> Option<Answer<'_>> {
+            let filtered_event = FilteredEvent {
+                kind: EventKind::Training,
+                title: "Training",
+                start: at("2024-03-12 19:30"),
+                holiday: None,
+            };
+            let event = AnsweredEvent {
+                profile_id: "123",
+                team: None,
+                event: &filtered_event,
+                end: at("2024-03-12 21:00"),
+                deadline: Some(at("2024-03-11 18:00")),
+            };
+            let answerers = Answerers {
+                absences: &self.absences,
+                conflict_answers: &self.conflict_answers,
+                rules: &self.rules,
+                deadlines: &self.deadlines,
+            };
+            let state = AnswerState {
+                resolved,
+                declined_for_absence,
+                answer_before_absence,
+            };
+            resolve_answer(
+                &event,
+                state,
+                &answerers,
+                || Some(self.busy_times.clone()),
+                self.now.date(),
+                self.now,
+            )
+        }
+    }
+
+    fn source(answer: Option<Answer>) -> Option<(Attendance, AnswerSource)> {
+        answer.map(|answer| (answer.attendance, answer.source))
+    }
+
+    #[test]
+    fn declines_absences_first() {
+        let setup = Setup::new();
+
+        assert_eq!(
+            setup.answer(Some(Accepted), false),
+            Some(Answer {
+                attendance: Declined,
+                source: AnswerSource::Absence,
+                reason: "Ferien",
+            })
+        );
+        // an answer given after the decline stands
+        assert_eq!(
+            source(setup.answer(Some(Accepted), true)),
+            Some((Accepted, AnswerSource::Synced))
+        );
+    }
+
+    #[test]
+    fn keeps_the_answer_of_the_attendee() {
+        let mut setup = Setup::new();
+        setup.absences = Absences::new(Vec::new(), None);
+
+        assert_eq!(
+            setup.answer(Some(Declined), false),
+            Some(Answer {
+                attendance: Declined,
+                source: AnswerSource::Synced,
+                reason: "-",
+            })
+        );
+    }
+
+    #[test]
+    fn answers_conflicts_before_rules() {
+        let mut setup = Setup::new();
+        setup.absences = Absences::new(Vec::new(), None);
+
+        assert_eq!(
+            setup.answer(None, false),
+            Some(Answer {
+                attendance: Unsure,
+                source: AnswerSource::Conflict(Some("Elternabend".to_owned())),
+                reason: "Konflikt",
+            })
+        );
+
+        setup.busy_times[0].end = at("2024-03-12 19:30");
+        assert_eq!(
+            source(setup.answer(None, false)),
+            Some((Accepted, AnswerSource::Rule))
+        );
+    }
+
+    #[test]
+    fn gives_the_default_answer_before_the_deadline_last() {
+        let mut setup = Setup::new();
+        setup.absences = Absences::new(Vec::new(), None);
+        setup.busy_times.clear();
+        setup.rules = DauerzusageRules::default();
+
+        assert_eq!(
+            source(setup.answer(None, false)),
+            Some((Declined, AnswerSource::Deadline(at("2024-03-11 18:00"))))
+        );
+
+        setup.now = at("2024-03-11 15:00");
+        assert_eq!(setup.answer(None, false), None);
+    }
+
+    #[test]
+    fn gives_back_the_answer_from_before_an_ended_absence() {
+        let mut setup = Setup::new();
+        setup.absences = Absences::new(Vec::new(), None);
+
+        assert_eq!(
+            source(setup.answer_after_absence(Some(Declined), true, Some(Accepted))),
+            Some((Accepted, AnswerSource::AbsenceEnded))
+        );
+        // without an answer from before, the event is answered like a new one
+        assert_eq!(
+            source(setup.answer_after_absence(Some(Declined), true, None)),
+            Some((
+                Unsure,
+                AnswerSource::Conflict(Some("Elternabend".to_owned()))
+            ))
+        );
+        setup.busy_times.clear();
+        setup.rules = DauerzusageRules::default();
+        setup.now = at("2024-03-11 15:00");
+        assert_eq!(
+            source(setup.answer_after_absence(Some(Declined), true, None)),
+            Some((Unsure, AnswerSource::AbsenceEnded))
+        );
+        // an answer given during the absence stands
+        assert_eq!(
+            source(setup.answer_after_absence(Some(Accepted), true, Some(Unsure))),
+            Some((Accepted, AnswerSource::Synced))
+        );
+    }
+
+    #[test]
+    fn keeps_the_decline_while_the_absence_lasts() {
+        let setup = Setup::new();
+
+        assert_eq!(
+            source(setup.answer_after_absence(Some(Declined), true, Some(Accepted))),
+            Some((Declined, AnswerSource::Synced))
+        );
+    }
+}
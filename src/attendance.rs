@@ -0,0 +1,342 @@
+use crate::{calendar::Attendance, error::AutospielerError, read_optional_env};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+};
+
+/// Decides which answer is kept when the attendee answered in the calendar and in
+/// Spielerplus since the last sync.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictPolicy {
+    /// The later answer wins. Spielerplus doesn't tell when an answer was given, so a
+    /// Spielerplus answer counts from the sync that first saw it.
+    LastWriterWins,
+    Calendar,
+    Spielerplus,
+}
+
+impl ConflictPolicy {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        match read_optional_env("ATTENDANCE_CONFLICT_POLICY")?.as_deref() {
+            None | Some("last-writer-wins") => Ok(ConflictPolicy::LastWriterWins),
+            Some("calendar") => Ok(ConflictPolicy::Calendar),
+            Some("spielerplus") => Ok(ConflictPolicy::Spielerplus),
+            Some(other) => Err(AutospielerError::config(format!(
+                "unknown ATTENDANCE_CONFLICT_POLICY '{other}', expected 'last-writer-wins', 'calendar' or 'spielerplus'"
+            ))),
+        }
+    }
+}
+
+/// The answers of one event as they were after the last sync.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+struct SyncedAttendance {
+    calendar: Option<Attendance>,
+    calendar_time: Option<DateTime<Utc>>,
+    spielerplus: Option<Attendance>,
+    spielerplus_time: Option<DateTime<Utc>>,
+    /// Whether Autospieler declined the event because the attendee is absent.
+    #[serde(default)]
+    declined_for_absence: bool,
+    /// The answer the event had before it was declined for an absence.
+    #[serde(default)]
+    answer_before_absence: Option<Attendance>,
+    /// Whether the attendee was reminded of the participation deadline.
+    #[serde(default)]
+    reminder_sent: bool,
+    /// Whether the calendar says that the answer couldn't be applied to the locked event.
+    #[serde(default)]
+    locked_noted: bool,
+    /// When Autospieler first tried to create the calendar event, until it shows up.
+    #[serde(default)]
+    create_attempt: Option<DateTime<Utc>>,
+}
+
+/// Remembers the last synced answers per event, so a sync can tell on which side an
+/// answer changed instead of always overwriting Spielerplus with the calendar.
+pub struct AttendanceStore {
+    path: PathBuf,
+    policy: ConflictPolicy,
+    events: HashMap<String, SyncedAttendance>,
+}
+
+impl AttendanceStore {
+    pub fn load(path: PathBuf, policy: ConflictPolicy) -> io::Result<Self> {
+        let events = if path.exists() {
+            match serde_json::from_str(&fs::read_to_string(&path)?) {
+                Ok(events) => events,
+                Err(err) => {
+                    log::warn!(
+                        "Ignoring unreadable attendance state {}: {err}",
+                        path.display()
+                    );
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+
+        Ok(AttendanceStore {
+            path,
+            policy,
+            events,
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&self.events)?)?;
+        Ok(())
+    }
+
+    /// Forgets the events that aren't in `spielerplus_ids` anymore.
+    pub fn retain(&mut self, spielerplus_ids: &HashSet<&str>) {
+        self.events
+            .retain(|spielerplus_id, _| spielerplus_ids.contains(spielerplus_id.as_str()));
+    }
+
+    /// Records the current answers of an event and decides which answer Spielerplus
+    /// should have. `calendar_time` is when the attendee responded in the calendar, if
+    /// the calendar tells. Once Spielerplus has been changed to the returned answer, call
+    /// [`AttendanceStore::set_spielerplus`].
+    pub fn resolve(
+        &mut self,
+        spielerplus_id: &str,
+        calendar: Option<Attendance>,
+        calendar_time: Option<DateTime<Utc>>,
+        spielerplus: Option<Attendance>,
+    ) -> Option<Attendance> {
+        let now = Utc::now();
+        let known = self.events.contains_key(spielerplus_id);
+        let synced = self.events.entry(spielerplus_id.to_owned()).or_default();
+
+        // Without a record, neither side is known to have changed, so answers only get
+        // the time the calendar reports. Spielerplus answers then count as the oldest.
+        let responded_again = calendar_time.is_some() && calendar_time != synced.calendar_time;
+        let calendar_changed = known && (synced.calendar != calendar || responded_again);
+        let spielerplus_changed = known && synced.spielerplus != spielerplus;
+        if synced.calendar != calendar || responded_again || !known {
+            synced.calendar = calendar;
+            synced.calendar_time = calendar_time.or(known.then_some(now));
+        }
+        if synced.spielerplus != spielerplus || !known {
+            synced.spielerplus = spielerplus;
+            synced.spielerplus_time = known.then_some(now);
+        }
+
+        let Some(calendar) = calendar else {
+            return spielerplus;
+        };
+        let calendar_wins = match (spielerplus, self.policy) {
+            (None, _) => true,
+            // an answer that only changed on one side wins, the policy decides the rest
+            _ if calendar_changed != spielerplus_changed => calendar_changed,
+            (_, ConflictPolicy::Calendar) => true,
+            (_, ConflictPolicy::Spielerplus) => false,
+            // on a tie the calendar wins, as it's the side the attendee is invited on
+            (_, ConflictPolicy::LastWriterWins) => synced.calendar_time >= synced.spielerplus_time,
+        };
+
+        if calendar_wins {
+            Some(calendar)
+        } else {
+            spielerplus
+        }
+    }
+
+    /// Whether the event was declined for an absence before, so another answer given
+    /// since stands.
+    pub fn declined_for_absence(&self, spielerplus_id: &str) -> bool {
+        self.events
+            .get(spielerplus_id)
+            .is_some_and(|synced| synced.declined_for_absence)
+    }
+
+    /// The answer the event had before it was declined for an absence.
+    pub fn answer_before_absence(&self, spielerplus_id: &str) -> Option<Attendance> {
+        self.events
+            .get(spielerplus_id)
+            .and_then(|synced| synced.answer_before_absence)
+    }
+
+    /// Records that the event is declined in Spielerplus for an absence, instead of
+    /// `answer_before`. That's the latest answer, so a calendar response given before
+    /// doesn't overwrite it.
+    pub fn decline_for_absence(&mut self, spielerplus_id: &str, answer_before: Option<Attendance>) {
+        let synced = self.events.entry(spielerplus_id.to_owned()).or_default();
+        synced.spielerplus = Some(Attendance::Declined);
+        synced.spielerplus_time = Some(Utc::now());
+        synced.declined_for_absence = true;
+        synced.answer_before_absence = answer_before;
+    }
+
+    /// Records that the event isn't during an absence anymore, so it's declined again
+    /// if an absence covers it later.
+    pub fn absence_ended(&mut self, spielerplus_id: &str) {
+        if let Some(synced) = self.events.get_mut(spielerplus_id) {
+            synced.declined_for_absence = false;
+            synced.answer_before_absence = None;
+        }
+    }
+
+    pub fn reminder_sent(&self, spielerplus_id: &str) -> bool {
+        self.events
+            .get(spielerplus_id)
+            .is_some_and(|synced| synced.reminder_sent)
+    }
+
+    pub fn set_reminder_sent(&mut self, spielerplus_id: &str) {
+        let synced = self.events.entry(spielerplus_id.to_owned()).or_default();
+        synced.reminder_sent = true;
+    }
+
+    /// When the calendar event was first tried to be created. The time is kept until
+    /// [`AttendanceStore::created`], so retries are recognized as such.
+    pub fn create_attempt(&mut self, spielerplus_id: &str) -> DateTime<Utc> {
+        let synced = self.events.entry(spielerplus_id.to_owned()).or_default();
+        *synced.create_attempt.get_or_insert_with(Utc::now)
+    }
+
+    /// Records that the calendar event exists, so creating it again is a new attempt.
+    pub fn created(&mut self, spielerplus_id: &str) {
+        if let Some(synced) = self.events.get_mut(spielerplus_id) {
+            synced.create_attempt = None;
+        }
+    }
+
+    pub fn locked_noted(&self, spielerplus_id: &str) -> bool {
+        self.events
+            .get(spielerplus_id)
+            .is_some_and(|synced| synced.locked_noted)
+    }
+
+    pub fn set_locked_noted(&mut self, spielerplus_id: &str) {
+        let synced = self.events.entry(spielerplus_id.to_owned()).or_default();
+        synced.locked_noted = true;
+    }
+
+    /// Records that Autospieler changed the answer in Spielerplus to the calendar's.
+    pub fn set_spielerplus(&mut self, spielerplus_id: &str, attendance: Attendance) {
+        if let Some(synced) = self.events.get_mut(spielerplus_id) {
+            synced.spielerplus = Some(attendance);
+            synced.spielerplus_time = synced.calendar_time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use Attendance::{Accepted, Declined, Unsure};
+
+    const ID: &str = "12345";
+
+    /// A store that last synced `Accepted` on both sides, answered in the calendar a day ago.
+    fn synced_store(policy: ConflictPolicy) -> AttendanceStore {
+        let mut store = AttendanceStore {
+            path: PathBuf::new(),
+            policy,
+            events: HashMap::new(),
+        };
+        let answered = Utc::now() - Duration::days(1);
+        assert_eq!(
+            store.resolve(ID, Some(Accepted), Some(answered), Some(Accepted)),
+            Some(Accepted)
+        );
+        store
+    }
+
+    const POLICIES: [ConflictPolicy; 3] = [
+        ConflictPolicy::LastWriterWins,
+        ConflictPolicy::Calendar,
+        ConflictPolicy::Spielerplus,
+    ];
+
+    #[test]
+    fn keeps_the_answer_if_nothing_changed() {
+        for policy in POLICIES {
+            let mut store = synced_store(policy);
+            let calendar_time = store.events[ID].calendar_time;
+
+            assert_eq!(
+                store.resolve(ID, Some(Accepted), calendar_time, Some(Accepted)),
+                Some(Accepted),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn takes_an_answer_only_changed_in_spielerplus() {
+        for policy in POLICIES {
+            let mut store = synced_store(policy);
+            let calendar_time = store.events[ID].calendar_time;
+
+            assert_eq!(
+                store.resolve(ID, Some(Accepted), calendar_time, Some(Declined)),
+                Some(Declined),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn takes_an_answer_only_changed_in_the_calendar() {
+        for policy in POLICIES {
+            let mut store = synced_store(policy);
+
+            assert_eq!(
+                store.resolve(ID, Some(Declined), Some(Utc::now()), Some(Accepted)),
+                Some(Declined),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn lets_the_policy_decide_if_both_changed() {
+        let both_changed = |policy, calendar_time| {
+            let mut store = synced_store(policy);
+            store.resolve(ID, Some(Declined), Some(calendar_time), Some(Unsure))
+        };
+        let earlier = Utc::now() - Duration::hours(1);
+        let later = Utc::now() + Duration::hours(1);
+
+        assert_eq!(
+            both_changed(ConflictPolicy::Calendar, earlier),
+            Some(Declined)
+        );
+        assert_eq!(
+            both_changed(ConflictPolicy::Spielerplus, later),
+            Some(Unsure)
+        );
+        // Spielerplus answers count from the sync that sees them, i.e. now
+        assert_eq!(
+            both_changed(ConflictPolicy::LastWriterWins, earlier),
+            Some(Unsure)
+        );
+        assert_eq!(
+            both_changed(ConflictPolicy::LastWriterWins, later),
+            Some(Declined)
+        );
+    }
+
+    #[test]
+    fn fills_in_unanswered_events_from_the_calendar() {
+        for policy in POLICIES {
+            let mut store = synced_store(policy);
+
+            assert_eq!(
+                store.resolve(ID, Some(Unsure), Some(Utc::now()), None),
+                Some(Unsure),
+                "{policy:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use crate::{
+    calendar::{Attendance, CalendarBackend, CalendarEvent, CalendarEventMap, NewCalendarEvent},
+    error::AutospielerError,
+    ical::{self, Component, Property, SP_ID_PROPERTY},
+    read_env, read_optional_env,
+    retry::SendWithRetry,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH},
+    Method, Url,
+};
+
+/// Holds the Spielerplus user the event was created for, like `SP_USER_EMAIL` in Outlook.
+const SP_USER_EMAIL_PROPERTY: &str = "X-SPIELERPLUS-USER-EMAIL";
+
+/// Namespace for the UIDs of created events, so the same Spielerplus event always ends
+/// up in the same resource and creating it can safely be retried.
+const UID_NAMESPACE: uuid::Uuid = uuid::uuid!("0f5e3c1a-8f0e-4d43-9b1e-6f1c3a7d2e84");
+
+pub struct CalDavConfig {
+    /// URL of the calendar collection, e.g. `http://localhost:5232/user/calendar/`.
+    pub calendar_url: Url,
+    pub username: String,
+    pub password: Option<String>,
+    pub organizer_email: Option<String>,
+}
+
+impl CalDavConfig {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let mut calendar_url = read_env("CALDAV_CALENDAR_URL")?;
+        // relative resource names are resolved against the collection
+        if !calendar_url.ends_with('/') {
+            calendar_url.push('/');
+        }
+
+        Ok(CalDavConfig {
+            calendar_url: Url::parse(&calendar_url).map_err(|e| {
+                AutospielerError::config(format!("Could not parse CALDAV_CALENDAR_URL: {e}"))
+            })?,
+            username: read_env("CALDAV_USERNAME")?,
+            password: read_optional_env("CALDAV_PASSWORD")?,
+            organizer_email: read_optional_env("CALDAV_ORGANIZER_EMAIL")?,
+        })
+    }
+}
+
+/// [`CalendarBackend`] for a CalDAV calendar collection. Every event is stored as its
+/// own `.ics` resource, whose path is used as the event id.
+pub struct CalDavBackend {
+    client: Client,
+    config: CalDavConfig,
+    attendee_email: String,
+}
+
+fn status_error(response: Response, action: &str) -> AutospielerError {
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+    AutospielerError::Calendar(format!(
+        "CalDAV {action} failed with status code {status}: {}",
+        body.trim()
+    ))
+}
+
+impl CalDavBackend {
+    pub fn new(client: Client, config: CalDavConfig, attendee_email: &str) -> Self {
+        CalDavBackend {
+            client,
+            config,
+            attendee_email: attendee_email.to_owned(),
+        }
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .basic_auth(&self.config.username, self.config.password.as_ref())
+    }
+
+    fn resource_url(&self, event_id: &str) -> Result<Url, AutospielerError> {
+        self.config
+            .calendar_url
+            .join(event_id)
+            .map_err(|e| AutospielerError::Calendar(format!("invalid event id '{event_id}': {e}")))
+    }
+
+    fn uid(&self, spielerplus_id: &str) -> String {
+        uuid::Uuid::new_v5(
+            &UID_NAMESPACE,
+            format!(
+                "{}/{}/{spielerplus_id}",
+                self.config.calendar_url, self.attendee_email
+            )
+            .as_bytes(),
+        )
+        .to_string()
+    }
+
+    /// Reads the managed event from the calendar data of a resource, `None` if it
+    /// wasn't created by Autospieler for the configured attendee.
+    fn to_calendar_event(&self, event_id: &str, calendar_data: &str) -> Option<CalendarEvent> {
+        let calendar = Component::parse(calendar_data)?;
+        let event = calendar.components("VEVENT").next()?;
+
+        let user_email = event.property(SP_USER_EMAIL_PROPERTY)?.text_value();
+        if !user_email.eq_ignore_ascii_case(&self.attendee_email) {
+            return None;
+        }
+
+        Some(CalendarEvent {
+            id: event_id.to_owned(),
+            spielerplus_id: event.property(SP_ID_PROPERTY)?.text_value(),
+            subject: event
+                .property("SUMMARY")
+                .map(Property::text_value)
+                .unwrap_or_default(),
+            start: event.property("DTSTART")?.date_time_value()?,
+            end: event.property("DTEND")?.date_time_value()?,
+            attendance: ical::attendance_from_partstat(
+                event
+                    .attendee(&self.attendee_email)
+                    .and_then(|attendee| attendee.param("PARTSTAT")),
+            ),
+            response_time: None,
+        })
+    }
+
+    /// Fetches a resource with its ETag, `None` if it doesn't exist.
+    fn get_resource(&self, event_id: &str) -> Result<Option<(String, String)>, AutospielerError> {
+        let response = self
+            .request(Method::GET, self.resource_url(event_id)?)
+            .send_with_retry()?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(status_error(response, "GET"));
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        Ok(Some((etag, response.text()?)))
+    }
+
+    /// Changes every `VEVENT` of a resource with `modify` and writes it back.
+    fn modify_resource(
+        &self,
+        event_id: &str,
+        modify: impl Fn(&mut Component),
+    ) -> Result<(), AutospielerError> {
+        let (etag, calendar_data) = self
+            .get_resource(event_id)?
+            .ok_or_else(|| AutospielerError::Calendar(format!("event {event_id} not found")))?;
+        let mut calendar = Component::parse(&calendar_data).ok_or_else(|| {
+            AutospielerError::Calendar(format!("event {event_id} is not valid iCalendar"))
+        })?;
+
+        for vevent in calendar.components_mut("VEVENT") {
+            modify(vevent);
+        }
+
+        let mut request = self
+            .request(Method::PUT, self.resource_url(event_id)?)
+            .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+            .body(calendar.to_ical());
+        if !etag.is_empty() {
+            // don't overwrite a response the attendee saved in the meantime
+            request = request.header(IF_MATCH, etag);
+        }
+
+        let response = request.send_with_retry_if_throttled()?;
+        if !response.status().is_success() {
+            return Err(status_error(response, "PUT"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the `href` and `calendar-data` of every response in a `multistatus` body.
+fn parse_multistatus(body: &str) -> Result<Vec<(String, String)>, AutospielerError> {
+    let document = roxmltree::Document::parse(body)
+        .map_err(|e| AutospielerError::Calendar(format!("invalid CalDAV REPORT response: {e}")))?;
+
+    let is = |node: &roxmltree::Node, namespace: &str, name: &str| {
+        node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name
+    };
+
+    Ok(document
+        .descendants()
+        .filter(|node| is(node, "DAV:", "response"))
+        .filter_map(|response| {
+            let href = response
+                .descendants()
+                .find(|node| is(node, "DAV:", "href"))?
+                .text()?;
+            let calendar_data = response
+                .descendants()
+                .find(|node| is(node, "urn:ietf:params:xml:ns:caldav", "calendar-data"))?
+                .text()?;
+            Some((href.trim().to_owned(), calendar_data.to_owned()))
+        })
+        .collect())
+}
+
+impl CalendarBackend for CalDavBackend {
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <D:getetag/>
+    <C:calendar-data/>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{}T000000Z"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+            current_date.format("%Y%m%d")
+        );
+
+        let response = self
+            .request(
+                Method::from_bytes(b"REPORT").unwrap(),
+                self.config.calendar_url.clone(),
+            )
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body)
+            .send_with_retry()?;
+
+        if response.status().as_u16() != 207 {
+            return Err(status_error(response, "REPORT"));
+        }
+
+        let mut events = CalendarEventMap::new();
+        for (href, calendar_data) in parse_multistatus(&response.text()?)? {
+            if let Some(event) = self.to_calendar_event(&href, &calendar_data) {
+                if event.start.date() >= current_date {
+                    events.insert(event.spielerplus_id.clone(), event);
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        Ok(self
+            .get_resource(event_id)?
+            .and_then(|(_, calendar_data)| self.to_calendar_event(event_id, &calendar_data)))
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        let uid = self.uid(&event.spielerplus_id);
+
+        let mut vevent = Component::event(&uid, event);
+        if let Some(organizer_email) = &self.config.organizer_email {
+            vevent.push(Property::new(
+                "ORGANIZER",
+                &format!("mailto:{organizer_email}"),
+            ));
+        }
+        vevent.push(
+            Property::new("ATTENDEE", &format!("mailto:{}", self.attendee_email))
+                .with_param("CUTYPE", "INDIVIDUAL")
+                .with_param("ROLE", "REQ-PARTICIPANT")
+                .with_param("PARTSTAT", "NEEDS-ACTION")
+                .with_param("RSVP", "TRUE"),
+        );
+        vevent.push(Property::text(SP_USER_EMAIL_PROPERTY, &self.attendee_email));
+
+        let mut calendar = Component::calendar(None);
+        calendar.components.push(vevent);
+
+        // If-None-Match makes a repeated create fail with 412 instead of overwriting
+        // the attendee's response.
+        let response = self
+            .request(Method::PUT, self.resource_url(&format!("{uid}.ics"))?)
+            .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+            .header(IF_NONE_MATCH, "*")
+            .body(calendar.to_ical())
+            .send_with_retry()?;
+
+        match response.status().as_u16() {
+            200..=299 | 412 => Ok(()),
+            _ => Err(status_error(response, "PUT")),
+        }
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        self.modify_resource(event_id, |vevent| vevent.reschedule(start, end))
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        let response = self
+            .request(Method::DELETE, self.resource_url(event_id)?)
+            .send_with_retry()?;
+
+        match response.status().as_u16() {
+            200..=299 | 404 => Ok(()),
+            _ => Err(status_error(response, "DELETE")),
+        }
+    }
+
+    fn show_spielerplus_attendance(
+        &mut self,
+        event_id: &str,
+        subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        self.modify_resource(event_id, |vevent| {
+            vevent.set(Property::text("SUMMARY", subject))
+        })
+    }
+
+    fn update_event_body(&mut self, event_id: &str, body: &str) -> Result<(), AutospielerError> {
+        self.modify_resource(event_id, |vevent| {
+            vevent.set(Property::text("DESCRIPTION", body))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::{Reply, StubServer};
+
+    fn backend(calendar_url: &str) -> CalDavBackend {
+        CalDavBackend::new(
+            Client::new(),
+            CalDavConfig {
+                calendar_url: Url::parse(calendar_url).unwrap(),
+                username: "user".to_owned(),
+                password: None,
+                organizer_email: None,
+            },
+            "me@example.org",
+        )
+    }
+
+    fn calendar_data(user_email: &str, partstat: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:0b6e0c0c\r
+DTSTART;TZID=Europe/Zurich:20240502T190000\r
+DTEND:20240502T190000Z\r
+SUMMARY:[Zugesagt] Training\\, Halle 2\r
+ATTENDEE;PARTSTAT=ACCEPTED:mailto:coach@example.org\r
+ATTENDEE;CN=Me;PARTSTAT={partstat}:MAILTO:Me@Example.org\r
+X-SPIELERPLUS-ID:42\r
+X-SPIELERPLUS-USER-EMAIL:{user_email}\r
+END:VEVENT\r
+END:VCALENDAR\r
+"
+        )
+    }
+
+    #[test]
+    fn reads_multistatus_responses_with_any_prefix() {
+        let responses = parse_multistatus(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<multistatus xmlns="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/user/calendar/a.ics</href>
+    <propstat><prop><getetag>"1"</getetag><cal:calendar-data>BEGIN:VCALENDAR
+END:VCALENDAR
+</cal:calendar-data></prop></propstat>
+  </response>
+  <d:response xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+    <d:href> http://localhost:5232/user/calendar/b.ics </d:href>
+    <d:propstat><d:prop><c:calendar-data>BEGIN:VCALENDAR</c:calendar-data></d:prop></d:propstat>
+  </d:response>
+  <response>
+    <href>/user/calendar/without-data.ics</href>
+    <propstat><prop><getetag>"3"</getetag></prop></propstat>
+  </response>
+  <response xmlns:other="urn:example:other">
+    <href>/user/calendar/other-namespace.ics</href>
+    <propstat><prop><other:calendar-data>BEGIN:VCALENDAR</other:calendar-data></prop></propstat>
+  </response>
+</multistatus>"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            responses,
+            [
+                (
+                    "/user/calendar/a.ics".to_owned(),
+                    "BEGIN:VCALENDAR\nEND:VCALENDAR\n".to_owned()
+                ),
+                (
+                    "http://localhost:5232/user/calendar/b.ics".to_owned(),
+                    "BEGIN:VCALENDAR".to_owned()
+                ),
+            ]
+        );
+        assert!(parse_multistatus("<multistatus").is_err());
+    }
+
+    #[test]
+    fn resolves_relative_and_absolute_hrefs() {
+        let backend = backend("http://localhost:5232/user/calendar/");
+        let expected = "http://localhost:5232/user/calendar/a.ics";
+
+        assert_eq!(backend.resource_url("a.ics").unwrap().as_str(), expected);
+        assert_eq!(
+            backend
+                .resource_url("/user/calendar/a.ics")
+                .unwrap()
+                .as_str(),
+            expected
+        );
+        assert_eq!(backend.resource_url(expected).unwrap().as_str(), expected);
+    }
+
+    #[test]
+    fn reads_the_events_of_the_attendee() {
+        let backend = backend("http://localhost:5232/user/calendar/");
+
+        let event = backend
+            .to_calendar_event(
+                "/user/calendar/a.ics",
+                &calendar_data("ME@example.org", "DECLINED"),
+            )
+            .unwrap();
+        assert_eq!(event.id, "/user/calendar/a.ics");
+        assert_eq!(event.spielerplus_id, "42");
+        assert_eq!(event.subject, "[Zugesagt] Training, Halle 2");
+        assert_eq!(event.start.to_string(), "2024-05-02 19:00:00");
+        // UTC is converted to local time
+        assert_eq!(event.end.to_string(), "2024-05-02 21:00:00");
+        // the answer of the other attendee doesn't count
+        assert_eq!(event.attendance, Some(Attendance::Declined));
+
+        assert!(backend
+            .to_calendar_event("a.ics", &calendar_data("you@example.org", "ACCEPTED"))
+            .is_none());
+        assert!(backend
+            .to_calendar_event("a.ics", "not iCalendar")
+            .is_none());
+    }
+
+    #[test]
+    fn maps_the_partstat_of_the_attendee() {
+        let backend = backend("http://localhost:5232/user/calendar/");
+        let attendance = |partstat| {
+            backend
+                .to_calendar_event("a.ics", &calendar_data("me@example.org", partstat))
+                .unwrap()
+                .attendance
+        };
+
+        assert_eq!(attendance("ACCEPTED"), Some(Attendance::Accepted));
+        assert_eq!(attendance("declined"), Some(Attendance::Declined));
+        assert_eq!(attendance("TENTATIVE"), Some(Attendance::Unsure));
+        assert_eq!(attendance("NEEDS-ACTION"), None);
+    }
+
+    #[test]
+    fn treats_an_existing_resource_as_created() {
+        let server = StubServer::start(|request| match request.url.as_str() {
+            "/user/calendar/" => Reply::json(500, "failed"),
+            _ => Reply::json(412, ""),
+        });
+        let mut backend = backend(&format!("{}/user/calendar/", server.base_url()));
+        let start = NaiveDateTime::parse_from_str("2024-05-02 19:00", "%Y-%m-%d %H:%M").unwrap();
+        let event = NewCalendarEvent {
+            subject: "Training".to_owned(),
+            body: String::new(),
+            start,
+            end: start,
+            location: String::new(),
+            spielerplus_id: "42".to_owned(),
+            first_attempt: None,
+        };
+
+        backend.create_event(&event).unwrap();
+        assert!(backend.list_events(start.date()).is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].url,
+            format!("/user/calendar/{}.ics", backend.uid("42"))
+        );
+        assert!(requests[0]
+            .body
+            .contains("X-SPIELERPLUS-USER-EMAIL:me@example.org"));
+    }
+}
@@ -0,0 +1,340 @@
+use crate::error::AutospielerError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Attendance {
+    #[serde(alias = "accepted")]
+    Accepted,
+    #[serde(alias = "unsure")]
+    Unsure,
+    #[serde(alias = "declined")]
+    Declined,
+}
+
+impl std::str::FromStr for Attendance {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "accepted" => Ok(Attendance::Accepted),
+            "unsure" => Ok(Attendance::Unsure),
+            "declined" => Ok(Attendance::Declined),
+            other => Err(format!(
+                "unknown answer '{other}', expected 'accepted', 'unsure' or 'declined'"
+            )),
+        }
+    }
+}
+
+/// An event Autospieler created in the calendar for a Spielerplus event.
+#[derive(Clone)]
+pub struct CalendarEvent {
+    /// Id of the event in the calendar.
+    pub id: String,
+    pub spielerplus_id: String,
+    pub subject: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// Response of the attendee, `None` if they haven't answered yet.
+    pub attendance: Option<Attendance>,
+    /// When the attendee responded, if the calendar tells.
+    pub response_time: Option<DateTime<Utc>>,
+}
+
+pub struct NewCalendarEvent {
+    pub subject: String,
+    pub body: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub location: String,
+    pub spielerplus_id: String,
+    /// When Autospieler first tried to create the event. It stays the same while a
+    /// create is retried and changes when a deleted or cancelled event is created again.
+    pub first_attempt: Option<DateTime<Utc>>,
+}
+
+pub enum CalendarChange {
+    Create(NewCalendarEvent),
+    UpdateTime {
+        event_id: String,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    },
+    Cancel {
+        event_id: String,
+    },
+    /// Shows the answer given in Spielerplus, see [`subject_with_attendance`].
+    ShowSpielerplusAttendance {
+        event_id: String,
+        subject: String,
+        attendance: Option<Attendance>,
+    },
+    UpdateBody {
+        event_id: String,
+        body: String,
+    },
+}
+
+impl CalendarChange {
+    pub fn description(&self) -> String {
+        match self {
+            CalendarChange::Create(event) => format!("create event {}", event.spielerplus_id),
+            CalendarChange::UpdateTime { event_id, .. } => format!("update event {event_id}"),
+            CalendarChange::Cancel { event_id } => format!("cancel event {event_id}"),
+            CalendarChange::ShowSpielerplusAttendance { event_id, .. } => {
+                format!("show the Spielerplus answer in event {event_id}")
+            }
+            CalendarChange::UpdateBody { event_id, .. } => {
+                format!("update the body of event {event_id}")
+            }
+        }
+    }
+}
+
+/// Subject prefixes showing the answer given in Spielerplus, e.g. in the app.
+const ATTENDANCE_PREFIXES: [(Attendance, &str); 3] = [
+    (Attendance::Accepted, "[Zugesagt] "),
+    (Attendance::Unsure, "[Unsicher] "),
+    (Attendance::Declined, "[Abgesagt] "),
+];
+
+/// Prefixes `subject` with the Spielerplus answer, so it's visible in every calendar
+/// client even if the attendee hasn't responded to the invitation.
+pub fn subject_with_attendance(subject: &str, attendance: Option<Attendance>) -> String {
+    let (_, subject) = attendance_from_subject(subject);
+    match ATTENDANCE_PREFIXES
+        .iter()
+        .find(|(prefixed, _)| Some(*prefixed) == attendance)
+    {
+        Some((_, prefix)) => format!("{prefix}{subject}"),
+        None => subject.to_owned(),
+    }
+}
+
+/// Splits a subject written by [`subject_with_attendance`] into the Spielerplus answer
+/// and the plain subject.
+pub fn attendance_from_subject(subject: &str) -> (Option<Attendance>, &str) {
+    ATTENDANCE_PREFIXES
+        .iter()
+        .find_map(|(attendance, prefix)| Some((Some(*attendance), subject.strip_prefix(prefix)?)))
+        .unwrap_or((None, subject))
+}
+
+/// A time the attendee is busy with an appointment.
+#[derive(Clone)]
+pub struct BusyTime {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// Subject of the appointment, if the calendar shares it.
+    pub subject: Option<String>,
+}
+
+impl BusyTime {
+    pub fn overlaps(&self, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// Automatic replies the attendee turned on in the calendar, like "out of office".
+pub struct OutOfOffice {
+    /// Start of the scheduled period, `None` if they're turned on until further notice.
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+}
+
+impl OutOfOffice {
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        self.start.is_none_or(|start| start <= time) && self.end.is_none_or(|end| time < end)
+    }
+}
+
+/// Managed events keyed by their Spielerplus id.
+pub type CalendarEventMap = HashMap<String, CalendarEvent>;
+
+/// A calendar Autospieler keeps in sync with Spielerplus. Every backend only deals with
+/// the events it created itself and with the response of the one configured attendee.
+pub trait CalendarBackend {
+    /// Upcoming events managed by Autospieler, starting at `current_date`.
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError>;
+
+    /// A single event, `None` if it doesn't exist (anymore) or isn't managed by Autospieler.
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError>;
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError>;
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError>;
+
+    /// Cancels the event, notifying the attendee if the calendar supports it.
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError>;
+
+    /// Replaces the subject of the event with `subject`, which shows the Spielerplus
+    /// answer `attendance`. Backends that can't change an event without sending the
+    /// attendee a new invitation keep the default, which leaves the event as it is.
+    fn show_spielerplus_attendance(
+        &mut self,
+        _event_id: &str,
+        _subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        Ok(())
+    }
+
+    /// Replaces the body of the event. Like with [`CalendarBackend::show_spielerplus_attendance`],
+    /// backends that would have to send a new invitation keep the default, which does nothing.
+    fn update_event_body(&mut self, _event_id: &str, _body: &str) -> Result<(), AutospielerError> {
+        Ok(())
+    }
+
+    /// Times between `start` and `end` the attendee is busy, in any of their calendars.
+    /// Tentative appointments, like invitations they haven't answered yet, don't count.
+    fn busy_times(
+        &mut self,
+        _start: NaiveDateTime,
+        _end: NaiveDateTime,
+    ) -> Result<Vec<BusyTime>, AutospielerError> {
+        Err(AutospielerError::config(
+            "the calendar backend doesn't support free/busy lookups",
+        ))
+    }
+
+    /// The automatic replies of the attendee, `None` if they're turned off.
+    fn out_of_office(&mut self) -> Result<Option<OutOfOffice>, AutospielerError> {
+        Err(AutospielerError::config(
+            "the calendar backend doesn't support automatic replies",
+        ))
+    }
+
+    /// Whether nobody can respond to the events of this calendar. Autospieler then only
+    /// reads Spielerplus and doesn't answer any event there, not even automatically.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Applies all changes of a sync and returns the result of every change in order.
+    /// Backends that can group requests override this.
+    fn apply_changes(&mut self, changes: &[CalendarChange]) -> Vec<Result<(), AutospielerError>> {
+        changes
+            .iter()
+            .map(|change| match change {
+                CalendarChange::Create(event) => self.create_event(event),
+                CalendarChange::UpdateTime {
+                    event_id,
+                    start,
+                    end,
+                } => self.update_event_time(event_id, *start, *end),
+                CalendarChange::Cancel { event_id } => self.cancel_event(event_id),
+                CalendarChange::ShowSpielerplusAttendance {
+                    event_id,
+                    subject,
+                    attendance,
+                } => self.show_spielerplus_attendance(event_id, subject, *attendance),
+                CalendarChange::UpdateBody { event_id, body } => {
+                    self.update_event_body(event_id, body)
+                }
+            })
+            .collect()
+    }
+
+    /// Makes sure the calendar keeps reporting changed events to `notification_url`.
+    fn ensure_change_notifications(
+        &mut self,
+        _notification_url: &str,
+        _client_state: &str,
+    ) -> Result<(), AutospielerError> {
+        Err(AutospielerError::config(
+            "the calendar backend doesn't support change notifications",
+        ))
+    }
+}
+
+/// Keeps the events in memory only, so nothing outlives the process. Handy for a dry
+/// run against Spielerplus and for exercising the sync without a real calendar.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    events: HashMap<String, CalendarEvent>,
+    next_id: u64,
+}
+
+impl CalendarBackend for InMemoryBackend {
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        Ok(self
+            .events
+            .values()
+            .filter(|event| event.start.date() >= current_date)
+            .map(|event| (event.spielerplus_id.clone(), event.clone()))
+            .collect())
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        Ok(self.events.get(event_id).cloned())
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        self.next_id += 1;
+        let id = format!("memory-{}", self.next_id);
+        self.events.insert(
+            id.clone(),
+            CalendarEvent {
+                id,
+                spielerplus_id: event.spielerplus_id.clone(),
+                subject: event.subject.clone(),
+                start: event.start,
+                end: event.end,
+                attendance: None,
+                response_time: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        let event = self
+            .events
+            .get_mut(event_id)
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?;
+        event.start = start;
+        event.end = end;
+        Ok(())
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        self.events.remove(event_id);
+        Ok(())
+    }
+
+    fn show_spielerplus_attendance(
+        &mut self,
+        event_id: &str,
+        subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        let event = self
+            .events
+            .get_mut(event_id)
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?;
+        event.subject = subject.to_owned();
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{calendar::Attendance, error::AutospielerError, filters::EventKind, read_optional_env};
+
+/// How unanswered events are answered when the attendee is busy with something else,
+/// from `CALENDAR_CONFLICT_ANSWERS`, e.g. `training=declined,game=unsure`.
+pub struct ConflictAnswers {
+    answers: Vec<(EventKind, Attendance)>,
+    /// Reason given in Spielerplus, from `CALENDAR_CONFLICT_REASON`.
+    pub reason: String,
+}
+
+/// Reads a value the way it's written in the JSON config files.
+fn parse<T: serde::de::DeserializeOwned>(value: &str) -> Option<T> {
+    serde_json::from_value(serde_json::Value::from(value.trim())).ok()
+}
+
+impl ConflictAnswers {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let reason =
+            read_optional_env("CALENDAR_CONFLICT_REASON")?.unwrap_or("Terminkonflikt".to_owned());
+        let list = read_optional_env("CALENDAR_CONFLICT_ANSWERS")?.unwrap_or_default();
+        Self::parse(&list, reason)
+    }
+
+    /// Reads a list like `training=declined,game=unsure`.
+    pub fn parse(list: &str, reason: String) -> Result<Self, AutospielerError> {
+        let mut answers = Vec::new();
+        for entry in list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let parsed = entry
+                .split_once('=')
+                .and_then(|(kind, answer)| Some((parse(kind)?, parse(answer)?)));
+            answers.push(parsed.ok_or_else(|| {
+                AutospielerError::config(format!(
+                    "invalid CALENDAR_CONFLICT_ANSWERS entry '{entry}', expected e.g. 'training=declined' with 'training', 'game', 'tournament' or 'other' and 'accepted', 'unsure' or 'declined'"
+                ))
+            })?);
+        }
+
+        Ok(ConflictAnswers { answers, reason })
+    }
+
+    /// The answer for conflicting events of `kind`, `None` if they're left unanswered.
+    pub fn answer(&self, kind: EventKind) -> Option<Attendance> {
+        self.answers
+            .iter()
+            .find(|(answer_kind, _)| *answer_kind == kind)
+            .map(|(_, answer)| *answer)
+    }
+}
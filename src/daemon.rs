@@ -0,0 +1,214 @@
+use crate::{
+    error::AutospielerError,
+    local_time::{self, TimeWindow},
+    read_optional_env, read_parsed_env,
+};
+use chrono::NaiveTime;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub struct DaemonConfig {
+    pub interval: Duration,
+    pub jitter: Duration,
+    /// No sync is started in this time window.
+    pub quiet_hours: Option<TimeWindow>,
+}
+
+fn read_seconds_env(name: &str, default: u64) -> Result<Duration, AutospielerError> {
+    Ok(Duration::from_secs(read_parsed_env(name, default)?))
+}
+
+impl DaemonConfig {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let interval = read_seconds_env("DAEMON_INTERVAL_SECONDS", 15 * 60)?;
+        if interval.is_zero() {
+            return Err(AutospielerError::config(
+                "DAEMON_INTERVAL_SECONDS must be greater than 0",
+            ));
+        }
+
+        Ok(DaemonConfig {
+            interval,
+            jitter: read_seconds_env("DAEMON_JITTER_SECONDS", 60)?,
+            quiet_hours: read_optional_env("DAEMON_QUIET_HOURS")?
+                .map(|value| TimeWindow::parse(&value))
+                .transpose()?,
+        })
+    }
+
+    /// Whether a sync is started at the local time `now`, i.e. it's not in the quiet hours.
+    fn sync_due(&self, now: NaiveTime) -> bool {
+        !self
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|quiet_hours| quiet_hours.contains(now))
+    }
+
+    /// How long to wait before the next sync: the interval plus up to `jitter`.
+    fn next_sync_in(&self) -> Duration {
+        self.interval + random_jitter(self.jitter)
+    }
+}
+
+fn random_jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    // RandomState is seeded randomly per instance, which is plenty for spreading out requests.
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % max.as_millis() as u64)
+}
+
+pub enum DaemonJob {
+    /// The regular full sync.
+    Sync,
+    /// Graph reported a change on the Outlook event with this id.
+    OutlookEventChanged(String),
+}
+
+/// Waits for `duration`, handling changed events as they come in and returning
+/// early if `terminate` gets set.
+fn wait_for_next_sync<F>(
+    duration: Duration,
+    terminate: &AtomicBool,
+    changed_events: Option<&Receiver<String>>,
+    handle_job: &mut F,
+) where
+    F: FnMut(DaemonJob) -> Result<(), AutospielerError>,
+{
+    let step = Duration::from_millis(500);
+    let deadline = Instant::now() + duration;
+
+    while !terminate.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let Some(changed_events) = changed_events else {
+            thread::sleep(remaining.min(step));
+            continue;
+        };
+
+        match changed_events.recv_timeout(remaining.min(step)) {
+            Ok(outlook_event_id) => {
+                if let Err(err) = handle_job(DaemonJob::OutlookEventChanged(outlook_event_id)) {
+                    eprintln!("handling changed event failed: {err}");
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                eprintln!("change notification listener stopped");
+                thread::sleep(remaining.min(step));
+            }
+        }
+    }
+}
+
+/// Runs a [`DaemonJob::Sync`] every `config.interval` (plus jitter) until SIGTERM or SIGINT
+/// is received, and a [`DaemonJob::OutlookEventChanged`] for every id received on
+/// `changed_events` in between. A job that is already running when the signal arrives
+/// is finished first.
+pub fn run<F>(
+    config: &DaemonConfig,
+    changed_events: Option<Receiver<String>>,
+    mut handle_job: F,
+) -> Result<(), AutospielerError>
+where
+    F: FnMut(DaemonJob) -> Result<(), AutospielerError>,
+{
+    let terminate = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, Arc::clone(&terminate))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&terminate))?;
+
+    println!(
+        "Starting daemon, syncing every {}s (+ up to {}s jitter)",
+        config.interval.as_secs(),
+        config.jitter.as_secs()
+    );
+
+    while !terminate.load(Ordering::Relaxed) {
+        if config.sync_due(local_time::local_now().time()) {
+            if let Err(err) = handle_job(DaemonJob::Sync) {
+                eprintln!("sync failed: {err}");
+            }
+        } else {
+            log::info!("Skipping sync during quiet hours");
+        }
+
+        wait_for_next_sync(
+            config.next_sync_in(),
+            &terminate,
+            changed_events.as_ref(),
+            &mut handle_job,
+        );
+    }
+
+    println!("Received termination signal, shutting down");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(jitter_seconds: u64, quiet_hours: Option<&str>) -> DaemonConfig {
+        DaemonConfig {
+            interval: Duration::from_secs(15 * 60),
+            jitter: Duration::from_secs(jitter_seconds),
+            quiet_hours: quiet_hours.map(|window| TimeWindow::parse(window).unwrap()),
+        }
+    }
+
+    fn time(input: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(input, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn waits_the_interval_plus_jitter() {
+        let with_jitter = config(60, None);
+        for _ in 0..100 {
+            let next_sync_in = with_jitter.next_sync_in();
+            assert!(next_sync_in >= with_jitter.interval);
+            assert!(next_sync_in < with_jitter.interval + with_jitter.jitter);
+        }
+
+        let without_jitter = config(0, None);
+        assert_eq!(without_jitter.next_sync_in(), without_jitter.interval);
+    }
+
+    #[test]
+    fn skips_syncs_in_the_quiet_hours() {
+        assert!(config(60, None).sync_due(time("03:00")));
+
+        let evening = config(60, Some("18:00-22:00"));
+        assert!(evening.sync_due(time("17:59")));
+        assert!(!evening.sync_due(time("18:00")));
+        assert!(!evening.sync_due(time("21:59")));
+        assert!(evening.sync_due(time("22:00")));
+    }
+
+    #[test]
+    fn skips_syncs_in_quiet_hours_across_midnight() {
+        let night = config(60, Some("22:00-06:00"));
+
+        assert!(night.sync_due(time("21:59")));
+        assert!(!night.sync_due(time("22:00")));
+        assert!(!night.sync_due(time("00:00")));
+        assert!(!night.sync_due(time("05:59")));
+        assert!(night.sync_due(time("06:00")));
+        assert!(night.sync_due(time("12:00")));
+    }
+}
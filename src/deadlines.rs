@@ -0,0 +1,97 @@
+use crate::{
+    calendar::Attendance,
+    error::AutospielerError,
+    imip::{smtp_error, SmtpConfig},
+    read_env, read_optional_env, read_parsed_env,
+};
+use chrono::{Duration, NaiveDateTime};
+use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
+
+/// Mails a reminder to answer an event before its deadline passes.
+pub struct Reminders {
+    /// How long before the deadline, from `DEADLINE_REMINDER_HOURS`.
+    pub before: Duration,
+    from: Mailbox,
+    mailer: SmtpTransport,
+}
+
+impl Reminders {
+    pub fn send(&self, to: &str, subject: &str, text: String) -> Result<(), AutospielerError> {
+        let to = to
+            .parse::<Mailbox>()
+            .map_err(|e| AutospielerError::config(format!("invalid mail address {to}: {e}")))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(format!("Reminder: {subject}"))
+            .body(text)
+            .map_err(smtp_error)?;
+
+        self.mailer.send(&message).map_err(smtp_error)?;
+        Ok(())
+    }
+}
+
+/// What happens to unanswered events as their participation deadline comes closer.
+pub struct DeadlineConfig {
+    /// Answer given in Spielerplus if nobody answered, from `DEADLINE_DEFAULT_ANSWER`.
+    pub default_answer: Option<Attendance>,
+    /// How long before the deadline the default answer is given, from
+    /// `DEADLINE_DEFAULT_ANSWER_HOURS`.
+    pub default_answer_before: Duration,
+    pub reminders: Option<Reminders>,
+}
+
+impl DeadlineConfig {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let default_answer = read_optional_env("DEADLINE_DEFAULT_ANSWER")?
+            .map(|answer| answer.trim().parse::<Attendance>())
+            .transpose()
+            .map_err(|e| AutospielerError::config(format!("DEADLINE_DEFAULT_ANSWER: {e}")))?;
+
+        let reminders = match read_optional_env("DEADLINE_REMINDER_HOURS")? {
+            Some(hours) => {
+                let hours: i64 = hours.trim().parse().map_err(|e| {
+                    AutospielerError::config(format!(
+                        "Could not parse environment variable DEADLINE_REMINDER_HOURS: {e}"
+                    ))
+                })?;
+                let from = read_env("REMINDER_EMAIL_FROM")?;
+                Some(Reminders {
+                    before: Duration::hours(hours),
+                    from: from.parse().map_err(|e| {
+                        AutospielerError::config(format!("invalid mail address {from}: {e}"))
+                    })?,
+                    mailer: SmtpConfig::from_env()?.transport()?,
+                })
+            }
+            None => None,
+        };
+
+        Ok(DeadlineConfig {
+            default_answer,
+            default_answer_before: Duration::hours(read_parsed_env(
+                "DEADLINE_DEFAULT_ANSWER_HOURS",
+                2,
+            )?),
+            reminders,
+        })
+    }
+
+    /// The default answer, if it's time to give it for an event with `deadline`.
+    pub fn default_answer_due(
+        &self,
+        deadline: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Option<Attendance> {
+        let due = deadline - self.default_answer_before <= now && now < deadline;
+        self.default_answer.filter(|_| due)
+    }
+
+    /// The reminders, if it's time to send one for an event with `deadline`.
+    pub fn reminder_due(&self, deadline: NaiveDateTime, now: NaiveDateTime) -> Option<&Reminders> {
+        self.reminders
+            .as_ref()
+            .filter(|reminders| deadline - reminders.before <= now && now < deadline)
+    }
+}
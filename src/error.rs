@@ -0,0 +1,127 @@
+use crate::office::GraphError;
+use std::{error::Error, fmt, io};
+
+#[derive(Debug)]
+pub enum AutospielerError {
+    /// Missing or invalid configuration, e.g. an environment variable that isn't set.
+    Config(String),
+    /// Logging in to Spielerplus or a calendar failed.
+    Authentication(String),
+    /// The Spielerplus pages don't look like expected anymore, most likely because
+    /// Spielerplus changed their markup.
+    Markup(String),
+    /// Spielerplus answered a request with an error.
+    Spielerplus(String),
+    /// Spielerplus didn't take an answer to an event, e.g. because it requires a reason.
+    ParticipationRejected {
+        event_id: String,
+        message: String,
+    },
+    /// Spielerplus took an answer to an event without showing it, so it's unknown
+    /// whether it was applied.
+    ParticipationUnverified {
+        event_id: String,
+    },
+    Graph(GraphError),
+    /// A calendar backend other than Graph failed.
+    Calendar(String),
+    /// A request couldn't be sent or its response couldn't be read.
+    Network(reqwest::Error),
+    /// An I/O operation failed, e.g. reading or writing the local state.
+    Io(io::Error),
+    /// The sync went through, but some calendar changes failed.
+    PartialSync {
+        failed: usize,
+        total: usize,
+    },
+}
+
+impl AutospielerError {
+    pub fn config(message: impl Into<String>) -> Self {
+        AutospielerError::Config(message.into())
+    }
+
+    pub fn markup(message: impl Into<String>) -> Self {
+        AutospielerError::Markup(message.into())
+    }
+
+    /// Exit code of the process when it stops because of this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AutospielerError::Io(_) => 1,
+            AutospielerError::Config(_) => 2,
+            AutospielerError::Authentication(_)
+            | AutospielerError::Graph(GraphError::Authentication { .. }) => 3,
+            AutospielerError::Markup(_) => 4,
+            AutospielerError::Spielerplus(_)
+            | AutospielerError::ParticipationRejected { .. }
+            | AutospielerError::ParticipationUnverified { .. } => 5,
+            AutospielerError::Graph(GraphError::Network(_)) | AutospielerError::Network(_) => 6,
+            AutospielerError::Graph(_) | AutospielerError::Calendar(_) => 7,
+            AutospielerError::PartialSync { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for AutospielerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutospielerError::Config(message) => write!(f, "configuration error: {message}"),
+            AutospielerError::Authentication(message) => {
+                write!(f, "login failed: {message}")
+            }
+            AutospielerError::Markup(message) => {
+                write!(f, "unexpected Spielerplus markup: {message}")
+            }
+            AutospielerError::Spielerplus(message) => {
+                write!(f, "Spielerplus request failed: {message}")
+            }
+            AutospielerError::ParticipationRejected { event_id, message } => {
+                write!(
+                    f,
+                    "Spielerplus rejected the answer to {event_id}: {message}"
+                )
+            }
+            AutospielerError::ParticipationUnverified { event_id } => write!(
+                f,
+                "could not verify the answer to {event_id}, Spielerplus doesn't show the participation"
+            ),
+            AutospielerError::Graph(err) => err.fmt(f),
+            AutospielerError::Calendar(message) => write!(f, "calendar request failed: {message}"),
+            AutospielerError::Network(err) => write!(f, "network error: {err}"),
+            AutospielerError::Io(err) => write!(f, "I/O error: {err}"),
+            AutospielerError::PartialSync { failed, total } => {
+                write!(f, "{failed} of {total} calendar changes failed")
+            }
+        }
+    }
+}
+
+impl Error for AutospielerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AutospielerError::Graph(err) => Some(err),
+            AutospielerError::Network(err) => Some(err),
+            AutospielerError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<GraphError> for AutospielerError {
+    fn from(err: GraphError) -> Self {
+        AutospielerError::Graph(err)
+    }
+}
+
+impl From<reqwest::Error> for AutospielerError {
+    fn from(err: reqwest::Error) -> Self {
+        AutospielerError::Network(err)
+    }
+}
+
+impl From<io::Error> for AutospielerError {
+    fn from(err: io::Error) -> Self {
+        AutospielerError::Io(err)
+    }
+}
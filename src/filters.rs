@@ -0,0 +1,246 @@
+use crate::{
+    error::AutospielerError, local_time::TimeWindow, read_optional_env, spielerplus::Team,
+};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Weekday};
+use std::fs;
+
+/// Kind of a Spielerplus event, read from the id of its panel.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Training,
+    Game,
+    Tournament,
+    Other,
+}
+
+impl EventKind {
+    pub fn from_panel_type(panel_type: &str) -> Self {
+        match panel_type {
+            "training" => EventKind::Training,
+            "game" => EventKind::Game,
+            "tournament" => EventKind::Tournament,
+            _ => EventKind::Other,
+        }
+    }
+}
+
+/// Days from `from` until `until`, both included.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub until: NaiveDate,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.until
+    }
+}
+
+/// A case-insensitive regular expression for event titles.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct TitlePattern(regex::Regex);
+
+impl TitlePattern {
+    pub fn is_match(&self, title: &str) -> bool {
+        self.0.is_match(title)
+    }
+}
+
+impl TryFrom<String> for TitlePattern {
+    type Error = AutospielerError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        regex::RegexBuilder::new(&source)
+            .case_insensitive(true)
+            .build()
+            .map(TitlePattern)
+            .map_err(|e| AutospielerError::config(format!("invalid title pattern: {e}")))
+    }
+}
+
+impl std::fmt::Display for TitlePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
+/// Which events of a team are synced. Every set condition has to be met.
+#[derive(serde::Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventFilter {
+    /// Names or profile ids of the teams this filter is for, all teams if empty.
+    pub teams: Vec<String>,
+    pub kinds: Vec<EventKind>,
+    pub exclude_kinds: Vec<EventKind>,
+    /// The title has to match one of these.
+    pub title_include: Vec<TitlePattern>,
+    pub title_exclude: Vec<TitlePattern>,
+    pub weekdays: Vec<Weekday>,
+    /// The event has to start in one of these.
+    pub time_windows: Vec<TimeWindow>,
+    /// The event has to be on a day in one of these.
+    pub dates: Vec<DateRange>,
+    /// Whether the event has to be on a public holiday or must not be, see `PUBLIC_HOLIDAYS`.
+    pub on_holiday: Option<bool>,
+    pub max_days_ahead: Option<i64>,
+}
+
+/// A Spielerplus event as far as filters are concerned.
+pub struct FilteredEvent<'a> {
+    pub kind: EventKind,
+    pub title: &'a str,
+    pub start: NaiveDateTime,
+    /// Name of the public holiday the event is on.
+    pub holiday: Option<&'a str>,
+}
+
+impl EventFilter {
+    pub fn is_for(&self, profile_id: &str, team: Option<&Team>) -> bool {
+        self.teams.is_empty()
+            || self.teams.iter().any(|entry| {
+                entry == profile_id
+                    || team.is_some_and(|team| entry.eq_ignore_ascii_case(&team.name))
+            })
+    }
+
+    /// Why the event is filtered out, `None` if it's synced.
+    pub fn rejection(&self, event: &FilteredEvent, today: NaiveDate) -> Option<String> {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind)
+            || self.exclude_kinds.contains(&event.kind)
+        {
+            return Some(format!("kind {:?} is filtered out", event.kind));
+        }
+        if !self.title_include.is_empty()
+            && !self
+                .title_include
+                .iter()
+                .any(|pattern| pattern.is_match(event.title))
+        {
+            return Some("title matches no title_include pattern".to_owned());
+        }
+        if let Some(pattern) = self
+            .title_exclude
+            .iter()
+            .find(|pattern| pattern.is_match(event.title))
+        {
+            return Some(format!("title matches title_exclude pattern '{pattern}'"));
+        }
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&event.start.weekday()) {
+            return Some(format!("{} is filtered out", event.start.weekday()));
+        }
+        if !self.time_windows.is_empty()
+            && !self
+                .time_windows
+                .iter()
+                .any(|window| window.contains(event.start.time()))
+        {
+            return Some("start is outside of the time windows".to_owned());
+        }
+        if !self.dates.is_empty()
+            && !self
+                .dates
+                .iter()
+                .any(|range| range.contains(event.start.date()))
+        {
+            return Some(format!("{} is outside of the dates", event.start.date()));
+        }
+        match (self.on_holiday, event.holiday) {
+            (Some(true), None) => return Some("not on a public holiday".to_owned()),
+            (Some(false), Some(holiday)) => return Some(format!("on {holiday}")),
+            _ => {}
+        }
+        if let Some(max_days_ahead) = self.max_days_ahead {
+            if (event.start.date() - today).num_days() > max_days_ahead {
+                return Some(format!("more than {max_days_ahead} days ahead"));
+            }
+        }
+        None
+    }
+}
+
+/// Filters from the JSON file in `EVENT_FILTERS_FILE`. Each team uses the first filter
+/// that is for it, teams without one get all their events synced.
+#[derive(Default)]
+pub struct EventFilters {
+    filters: Vec<EventFilter>,
+}
+
+impl EventFilters {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let Some(path) = read_optional_env("EVENT_FILTERS_FILE")? else {
+            return Ok(EventFilters::default());
+        };
+
+        let text = fs::read_to_string(&path).map_err(|e| {
+            AutospielerError::config(format!("Could not read EVENT_FILTERS_FILE {path}: {e}"))
+        })?;
+        let filters = serde_json::from_str(&text).map_err(|e| {
+            AutospielerError::config(format!("Could not parse EVENT_FILTERS_FILE {path}: {e}"))
+        })?;
+
+        Ok(EventFilters { filters })
+    }
+
+    pub fn for_team(&self, profile_id: &str, team: Option<&Team>) -> Option<&EventFilter> {
+        self.filters
+            .iter()
+            .find(|filter| filter.is_for(profile_id, team))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn filter(json: &str) -> EventFilter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn event(title: &str) -> FilteredEvent<'_> {
+        FilteredEvent {
+            kind: EventKind::Training,
+            title,
+            start: NaiveDate::from_ymd_opt(2024, 3, 12)
+                .unwrap()
+                .and_time(NaiveTime::from_hms_opt(19, 30, 0).unwrap()),
+            holiday: None,
+        }
+    }
+
+    #[test]
+    fn matches_titles_case_insensitively() {
+        let filter =
+            filter(r#"{"title_include": ["^(training|spiel)"], "title_exclude": ["GV \\d{4}"]}"#);
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        assert_eq!(filter.rejection(&event("Training Herren"), today), None);
+        assert!(filter
+            .rejection(&event("Vorstandssitzung"), today)
+            .is_some());
+        assert_eq!(
+            filter.rejection(&event("Spiel nach der GV 2024"), today),
+            Some("title matches title_exclude pattern 'GV \\d{4}'".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_title_patterns() {
+        let err = serde_json::from_str::<EventFilter>(r#"{"title_include": ["(Training"]}"#)
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("invalid title pattern"), "{err}");
+    }
+
+    #[test]
+    fn matches_nested_repetitions_in_linear_time() {
+        let pattern = TitlePattern::try_from("^(a+)+$".to_owned()).unwrap();
+
+        assert!(!pattern.is_match(&format!("{}b", "a".repeat(10_000))));
+    }
+}
@@ -0,0 +1,738 @@
+use crate::{
+    calendar::{Attendance, CalendarBackend, CalendarEvent, CalendarEventMap, NewCalendarEvent},
+    error::AutospielerError,
+    ical::TIME_ZONE,
+    read_env, read_optional_env,
+    retry::SendWithRetry,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    Url,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_GOOGLE_BASE_URL: &str = "https://www.googleapis.com/calendar/v3";
+const DEFAULT_GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const CALENDAR_SCOPE: &str = "https://www.googleapis.com/auth/calendar";
+
+/// Private extended properties, only visible to this calendar's copy of the event.
+const SP_ID_PROPERTY: &str = "spielerplusId";
+const SP_USER_EMAIL_PROPERTY: &str = "spielerplusUserEmail";
+
+/// Namespace for the ids of created events. Google accepts client-chosen ids and
+/// rejects a second create with the same id, so creating an event can safely be retried.
+const EVENT_ID_NAMESPACE: uuid::Uuid = uuid::uuid!("9d3a6c52-1b7e-4f0a-8c2d-5e4b7a1f6c93");
+
+/// Contents of a service account key file, as downloaded from the Google Cloud console.
+#[derive(serde::Deserialize)]
+pub struct GoogleServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    pub token_uri: Option<String>,
+}
+
+pub enum GoogleCredentials {
+    /// A service account, optionally acting as `subject` via domain-wide delegation.
+    /// Inviting attendees requires the delegation.
+    ServiceAccount {
+        key: GoogleServiceAccountKey,
+        subject: Option<String>,
+    },
+    /// An OAuth client with a refresh token of the calendar's owner.
+    RefreshToken {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+}
+
+pub struct GoogleConfig {
+    pub credentials: GoogleCredentials,
+    pub calendar_id: String,
+    pub base_url: String,
+    pub token_url: String,
+}
+
+impl GoogleConfig {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let credentials = match read_optional_env("GOOGLE_SERVICE_ACCOUNT_KEY_FILE")? {
+            Some(key_file) => {
+                let key = serde_json::from_str(&fs::read_to_string(&key_file)?).map_err(|e| {
+                    AutospielerError::config(format!("Could not parse {key_file}: {e}"))
+                })?;
+                GoogleCredentials::ServiceAccount {
+                    key,
+                    subject: read_optional_env("GOOGLE_IMPERSONATE_USER")?,
+                }
+            }
+            None => GoogleCredentials::RefreshToken {
+                client_id: read_env("GOOGLE_CLIENT_ID")?,
+                client_secret: read_env("GOOGLE_CLIENT_SECRET")?,
+                refresh_token: read_env("GOOGLE_REFRESH_TOKEN")?,
+            },
+        };
+
+        let key_token_uri = match &credentials {
+            GoogleCredentials::ServiceAccount { key, .. } => key.token_uri.clone(),
+            GoogleCredentials::RefreshToken { .. } => None,
+        };
+        let token_url = read_optional_env("GOOGLE_TOKEN_URL")?
+            .or(key_token_uri)
+            .unwrap_or_else(|| DEFAULT_GOOGLE_TOKEN_URL.to_owned());
+
+        Ok(GoogleConfig {
+            credentials,
+            calendar_id: read_optional_env("GOOGLE_CALENDAR_ID")?
+                .unwrap_or_else(|| "primary".to_owned()),
+            base_url: read_optional_env("GOOGLE_BASE_URL")?
+                .unwrap_or_else(|| DEFAULT_GOOGLE_BASE_URL.to_owned())
+                .trim_end_matches('/')
+                .to_owned(),
+            token_url,
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct GoogleTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(serde::Serialize)]
+struct GoogleJwtClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct GoogleErrorDetail {
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct GoogleErrorResponse {
+    error: GoogleErrorDetail,
+}
+
+fn status_error(response: Response) -> AutospielerError {
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+    let message = serde_json::from_str::<GoogleErrorResponse>(&body)
+        .map(|data| data.error.message)
+        .unwrap_or(body);
+    AutospielerError::Calendar(format!(
+        "Google Calendar request failed with status code {status}: {message}"
+    ))
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEventDateTime {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date_time: Option<String>,
+    /// Set instead of `date_time` if the event was made an all-day event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_zone: Option<String>,
+}
+
+impl GoogleEventDateTime {
+    fn local(date_time: NaiveDateTime) -> Self {
+        GoogleEventDateTime {
+            date_time: Some(date_time.format("%Y-%m-%dT%H:%M:%S").to_string()),
+            date: None,
+            time_zone: Some(TIME_ZONE.to_owned()),
+        }
+    }
+
+    /// Reads the local time. Responses are requested in [`TIME_ZONE`], so the offset
+    /// Google adds is the one of that time zone. All-day dates start at midnight.
+    fn to_local(&self) -> Result<NaiveDateTime, AutospielerError> {
+        match (&self.date_time, &self.date) {
+            (Some(date_time), _) => DateTime::parse_from_rfc3339(date_time)
+                .map(|date_time| date_time.naive_local())
+                .or_else(|_| NaiveDateTime::parse_from_str(date_time, "%Y-%m-%dT%H:%M:%S"))
+                .map_err(|e| {
+                    AutospielerError::Calendar(format!("invalid dateTime '{date_time}': {e}"))
+                }),
+            (None, Some(date)) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(|date| date.and_time(NaiveTime::MIN))
+                .map_err(|e| AutospielerError::Calendar(format!("invalid date '{date}': {e}"))),
+            (None, None) => Err(AutospielerError::Calendar(
+                "event time has neither a dateTime nor a date".to_owned(),
+            )),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleAttendee {
+    email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_status: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+struct GoogleExtendedProperties {
+    #[serde(default)]
+    private: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEvent {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(default)]
+    summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    start: GoogleEventDateTime,
+    end: GoogleEventDateTime,
+    #[serde(default)]
+    attendees: Vec<GoogleAttendee>,
+    #[serde(default)]
+    extended_properties: GoogleExtendedProperties,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEventList {
+    #[serde(default)]
+    items: Vec<GoogleEvent>,
+    next_page_token: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct GoogleUpdateEventSummaryRequest<'a> {
+    summary: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct GoogleUpdateEventDescriptionRequest<'a> {
+    description: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct GoogleUpdateEventTimeRequest {
+    start: GoogleEventDateTime,
+    end: GoogleEventDateTime,
+}
+
+/// [`CalendarBackend`] for a Google calendar. The Spielerplus id and user are kept in
+/// private extended properties and the attendee's `responseStatus` is the RSVP.
+pub struct GoogleCalendarBackend {
+    client: Client,
+    config: GoogleConfig,
+    attendee_email: String,
+    token: Option<(String, Instant)>,
+}
+
+impl GoogleCalendarBackend {
+    pub fn new(client: Client, config: GoogleConfig, attendee_email: &str) -> Self {
+        GoogleCalendarBackend {
+            client,
+            config,
+            attendee_email: attendee_email.to_owned(),
+            token: None,
+        }
+    }
+
+    fn request_token(&self) -> Result<(String, Instant), AutospielerError> {
+        let request = match &self.config.credentials {
+            GoogleCredentials::ServiceAccount { key, subject } => {
+                let now = chrono::Utc::now().timestamp();
+                let claims = GoogleJwtClaims {
+                    iss: &key.client_email,
+                    scope: CALENDAR_SCOPE,
+                    aud: &self.config.token_url,
+                    iat: now,
+                    exp: now + 3600,
+                    sub: subject.as_deref(),
+                };
+                let encoding_key = jsonwebtoken::EncodingKey::from_rsa_pem(
+                    key.private_key.as_bytes(),
+                )
+                .map_err(|e| {
+                    AutospielerError::config(format!("invalid service account private key: {e}"))
+                })?;
+                let assertion = jsonwebtoken::encode(
+                    &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+                    &claims,
+                    &encoding_key,
+                )
+                .map_err(|e| AutospielerError::Authentication(format!("Google: {e}")))?;
+
+                self.client.post(&self.config.token_url).form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", &assertion),
+                ])
+            }
+            GoogleCredentials::RefreshToken {
+                client_id,
+                client_secret,
+                refresh_token,
+            } => self.client.post(&self.config.token_url).form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+                ("refresh_token", refresh_token),
+            ]),
+        };
+
+        let response = request.send_with_retry()?;
+        if !response.status().is_success() {
+            return Err(AutospielerError::Authentication(format!(
+                "Google token request failed with status code {}: {}",
+                response.status(),
+                response.text().unwrap_or_default().trim()
+            )));
+        }
+
+        let data = response.json::<GoogleTokenResponse>()?;
+        Ok((
+            data.access_token,
+            Instant::now() + Duration::from_secs(data.expires_in),
+        ))
+    }
+
+    fn access_token(&mut self) -> Result<String, AutospielerError> {
+        let needs_refresh = match &self.token {
+            Some((_, expires_at)) => *expires_at <= Instant::now() + Duration::from_secs(5 * 60),
+            None => true,
+        };
+
+        if needs_refresh {
+            self.token = Some(self.request_token()?);
+        }
+
+        Ok(self.token.as_ref().unwrap().0.clone())
+    }
+
+    /// URL of the calendar's events, or of a single event.
+    fn events_url(&self, event_id: Option<&str>) -> Result<Url, AutospielerError> {
+        let mut url = Url::parse(&self.config.base_url).map_err(|e| {
+            AutospielerError::config(format!("Could not parse GOOGLE_BASE_URL: {e}"))
+        })?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| AutospielerError::config("GOOGLE_BASE_URL is not a valid base URL"))?;
+            segments
+                .push("calendars")
+                .push(&self.config.calendar_id)
+                .push("events");
+            if let Some(event_id) = event_id {
+                segments.push(event_id);
+            }
+        }
+        Ok(url)
+    }
+
+    fn request(
+        &mut self,
+        method: reqwest::Method,
+        url: Url,
+    ) -> Result<RequestBuilder, AutospielerError> {
+        let access_token = self.access_token()?;
+        Ok(self.client.request(method, url).bearer_auth(access_token))
+    }
+
+    fn event_id(&self, spielerplus_id: &str) -> String {
+        // uuids without dashes only contain characters Google allows in event ids
+        uuid::Uuid::new_v5(
+            &EVENT_ID_NAMESPACE,
+            format!(
+                "{}/{}/{spielerplus_id}",
+                self.config.calendar_id, self.attendee_email
+            )
+            .as_bytes(),
+        )
+        .simple()
+        .to_string()
+    }
+
+    /// Brings back a cancelled event with the id of `event`. Google keeps cancelled
+    /// events around, so their id can't be used for a new one.
+    fn restore_event(&mut self, mut event: GoogleEvent) -> Result<(), AutospielerError> {
+        let url = self.events_url(Some(&event.id))?;
+        let response = self
+            .request(reqwest::Method::GET, url.clone())?
+            .send_with_retry()?;
+        if !response.status().is_success() {
+            return Err(status_error(response));
+        }
+        if response.json::<GoogleEvent>()?.status.as_deref() != Some("cancelled") {
+            return Ok(());
+        }
+
+        event.status = Some("confirmed".to_owned());
+        let response = self
+            .request(reqwest::Method::PUT, url)?
+            .query(&[("sendUpdates", "all")])
+            .json(&event)
+            .send_with_retry()?;
+        if !response.status().is_success() {
+            return Err(status_error(response));
+        }
+        Ok(())
+    }
+
+    /// Maps the event to a [`CalendarEvent`], `None` if it's cancelled or wasn't
+    /// created by Autospieler for the configured attendee.
+    fn to_calendar_event(
+        &self,
+        event: &GoogleEvent,
+    ) -> Result<Option<CalendarEvent>, AutospielerError> {
+        let properties = &event.extended_properties.private;
+        let Some(spielerplus_id) = properties.get(SP_ID_PROPERTY) else {
+            return Ok(None);
+        };
+        if event.status.as_deref() == Some("cancelled")
+            || !properties
+                .get(SP_USER_EMAIL_PROPERTY)
+                .is_some_and(|email| email.eq_ignore_ascii_case(&self.attendee_email))
+        {
+            return Ok(None);
+        }
+
+        let response_status = event
+            .attendees
+            .iter()
+            .find(|attendee| attendee.email.eq_ignore_ascii_case(&self.attendee_email))
+            .and_then(|attendee| attendee.response_status.as_deref());
+
+        Ok(Some(CalendarEvent {
+            id: event.id.clone(),
+            spielerplus_id: spielerplus_id.clone(),
+            subject: event.summary.clone(),
+            start: event.start.to_local()?,
+            end: event.end.to_local()?,
+            attendance: match response_status {
+                None | Some("needsAction") => None,
+                Some("accepted") => Some(Attendance::Accepted),
+                Some("declined") => Some(Attendance::Declined),
+                _ => Some(Attendance::Unsure),
+            },
+            response_time: None,
+        }))
+    }
+}
+
+impl CalendarBackend for GoogleCalendarBackend {
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        let url = self.events_url(None)?;
+        let mut page_token: Option<String> = None;
+        let mut events = CalendarEventMap::new();
+
+        loop {
+            let mut query = vec![
+                (
+                    "privateExtendedProperty",
+                    format!("{SP_USER_EMAIL_PROPERTY}={}", self.attendee_email),
+                ),
+                ("timeMin", format!("{current_date}T00:00:00Z")),
+                ("timeZone", TIME_ZONE.to_owned()),
+                ("singleEvents", "true".to_owned()),
+                ("maxResults", "250".to_owned()),
+            ];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token.clone()));
+            }
+
+            let response = self
+                .request(reqwest::Method::GET, url.clone())?
+                .query(&query)
+                .send_with_retry()?;
+            if !response.status().is_success() {
+                return Err(status_error(response));
+            }
+
+            let data = response.json::<GoogleEventList>()?;
+            for event in &data.items {
+                if let Some(event) = self.to_calendar_event(event)? {
+                    if event.start.date() >= current_date {
+                        events.insert(event.spielerplus_id.clone(), event);
+                    }
+                }
+            }
+
+            match data.next_page_token {
+                Some(next_page_token) => page_token = Some(next_page_token),
+                None => break,
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        let url = self.events_url(Some(event_id))?;
+        let response = self
+            .request(reqwest::Method::GET, url)?
+            .query(&[("timeZone", TIME_ZONE)])
+            .send_with_retry()?;
+
+        match response.status().as_u16() {
+            404 | 410 => Ok(None),
+            status if (200..300).contains(&status) => {
+                self.to_calendar_event(&response.json::<GoogleEvent>()?)
+            }
+            _ => Err(status_error(response)),
+        }
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        let body = GoogleEvent {
+            id: self.event_id(&event.spielerplus_id),
+            status: None,
+            summary: event.subject.clone(),
+            description: Some(event.body.clone()),
+            location: Some(event.location.clone()),
+            start: GoogleEventDateTime::local(event.start),
+            end: GoogleEventDateTime::local(event.end),
+            attendees: vec![GoogleAttendee {
+                email: self.attendee_email.clone(),
+                response_status: None,
+            }],
+            extended_properties: GoogleExtendedProperties {
+                private: HashMap::from([
+                    (SP_ID_PROPERTY.to_owned(), event.spielerplus_id.clone()),
+                    (
+                        SP_USER_EMAIL_PROPERTY.to_owned(),
+                        self.attendee_email.clone(),
+                    ),
+                ]),
+            },
+        };
+
+        let url = self.events_url(None)?;
+        let response = self
+            .request(reqwest::Method::POST, url)?
+            .query(&[("sendUpdates", "all")])
+            .json(&body)
+            .send_with_retry()?;
+
+        match response.status().as_u16() {
+            200..=299 => Ok(()),
+            // the id is taken, either by an earlier attempt or by the same event that got
+            // cancelled before and has now shown up in Spielerplus again
+            409 => self.restore_event(body),
+            _ => Err(status_error(response)),
+        }
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        let url = self.events_url(Some(event_id))?;
+        let response = self
+            .request(reqwest::Method::PATCH, url)?
+            .query(&[("sendUpdates", "all")])
+            .json(&GoogleUpdateEventTimeRequest {
+                start: GoogleEventDateTime::local(start),
+                end: GoogleEventDateTime::local(end),
+            })
+            .send_with_retry()?;
+
+        if !response.status().is_success() {
+            return Err(status_error(response));
+        }
+        Ok(())
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        let url = self.events_url(Some(event_id))?;
+        let response = self
+            .request(reqwest::Method::DELETE, url)?
+            .query(&[("sendUpdates", "all")])
+            .send_with_retry()?;
+
+        match response.status().as_u16() {
+            200..=299 | 404 | 410 => Ok(()),
+            _ => Err(status_error(response)),
+        }
+    }
+
+    fn show_spielerplus_attendance(
+        &mut self,
+        event_id: &str,
+        subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        let url = self.events_url(Some(event_id))?;
+        let response = self
+            .request(reqwest::Method::PATCH, url)?
+            .query(&[("sendUpdates", "all")])
+            .json(&GoogleUpdateEventSummaryRequest { summary: subject })
+            .send_with_retry()?;
+
+        if !response.status().is_success() {
+            return Err(status_error(response));
+        }
+        Ok(())
+    }
+
+    fn update_event_body(&mut self, event_id: &str, body: &str) -> Result<(), AutospielerError> {
+        let url = self.events_url(Some(event_id))?;
+        let response = self
+            .request(reqwest::Method::PATCH, url)?
+            .query(&[("sendUpdates", "all")])
+            .json(&GoogleUpdateEventDescriptionRequest { description: body })
+            .send_with_retry()?;
+
+        if !response.status().is_success() {
+            return Err(status_error(response));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> GoogleCalendarBackend {
+        GoogleCalendarBackend::new(
+            Client::new(),
+            GoogleConfig {
+                credentials: GoogleCredentials::RefreshToken {
+                    client_id: "client".to_owned(),
+                    client_secret: "secret".to_owned(),
+                    refresh_token: "refresh".to_owned(),
+                },
+                calendar_id: "primary".to_owned(),
+                base_url: DEFAULT_GOOGLE_BASE_URL.to_owned(),
+                token_url: DEFAULT_GOOGLE_TOKEN_URL.to_owned(),
+            },
+            "me@example.org",
+        )
+    }
+
+    fn calendar_event(json: serde_json::Value) -> Option<CalendarEvent> {
+        backend()
+            .to_calendar_event(&serde_json::from_value(json).unwrap())
+            .unwrap()
+    }
+
+    fn event_json(response_status: &str) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "calendar#event",
+            "id": "0b6e0c0c",
+            "status": "confirmed",
+            "summary": "[Zugesagt] Training",
+            "start": {"dateTime": "2024-05-02T19:00:00+02:00", "timeZone": "Europe/Zurich"},
+            "end": {"dateTime": "2024-05-02T21:00:00+02:00", "timeZone": "Europe/Zurich"},
+            "attendees": [
+                {"email": "coach@example.org", "organizer": true, "responseStatus": "accepted"},
+                {"email": "Me@Example.org", "responseStatus": response_status}
+            ],
+            "extendedProperties": {
+                "private": {"spielerplusId": "42", "spielerplusUserEmail": "me@example.org"},
+                "shared": {"spielerplusId": "43"}
+            }
+        })
+    }
+
+    #[test]
+    fn reads_events_created_for_the_attendee() {
+        let event = calendar_event(event_json("declined")).unwrap();
+
+        assert_eq!(event.id, "0b6e0c0c");
+        assert_eq!(event.spielerplus_id, "42");
+        assert_eq!(event.subject, "[Zugesagt] Training");
+        assert_eq!(event.start.to_string(), "2024-05-02 19:00:00");
+        assert_eq!(event.end.to_string(), "2024-05-02 21:00:00");
+        // the organizer's answer doesn't count
+        assert_eq!(event.attendance, Some(Attendance::Declined));
+    }
+
+    #[test]
+    fn ignores_other_and_cancelled_events() {
+        let mut other_user = event_json("accepted");
+        other_user["extendedProperties"]["private"]["spielerplusUserEmail"] =
+            "you@example.org".into();
+        assert!(calendar_event(other_user).is_none());
+
+        let mut shared_only = event_json("accepted");
+        shared_only["extendedProperties"]["private"] = serde_json::json!({});
+        assert!(calendar_event(shared_only).is_none());
+
+        let mut cancelled = event_json("accepted");
+        cancelled["status"] = "cancelled".into();
+        assert!(calendar_event(cancelled).is_none());
+    }
+
+    #[test]
+    fn maps_the_response_status_of_the_attendee() {
+        let attendance = |response_status| {
+            calendar_event(event_json(response_status))
+                .unwrap()
+                .attendance
+        };
+
+        assert_eq!(attendance("accepted"), Some(Attendance::Accepted));
+        assert_eq!(attendance("declined"), Some(Attendance::Declined));
+        assert_eq!(attendance("tentative"), Some(Attendance::Unsure));
+        assert_eq!(attendance("needsAction"), None);
+
+        let mut uninvited = event_json("accepted");
+        uninvited["attendees"] = serde_json::json!([]);
+        assert_eq!(calendar_event(uninvited).unwrap().attendance, None);
+    }
+
+    #[test]
+    fn reads_all_day_and_zoneless_times() {
+        let mut all_day = event_json("accepted");
+        all_day["start"] = serde_json::json!({"date": "2024-05-02"});
+        all_day["end"] = serde_json::json!({"date": "2024-05-03"});
+        let event = calendar_event(all_day).unwrap();
+        assert_eq!(event.start.to_string(), "2024-05-02 00:00:00");
+        assert_eq!(event.end.to_string(), "2024-05-03 00:00:00");
+
+        let mut zoneless = event_json("accepted");
+        zoneless["start"] = serde_json::json!({"dateTime": "2024-05-02T19:00:00"});
+        assert_eq!(
+            calendar_event(zoneless).unwrap().start.to_string(),
+            "2024-05-02 19:00:00"
+        );
+
+        let mut timeless = event_json("accepted");
+        timeless["start"] = serde_json::json!({"timeZone": "Europe/Zurich"});
+        assert!(backend()
+            .to_calendar_event(&serde_json::from_value(timeless).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn writes_local_times_with_the_time_zone() {
+        let start = NaiveDateTime::parse_from_str("2024-05-02 19:00", "%Y-%m-%d %H:%M").unwrap();
+
+        assert_eq!(
+            serde_json::to_value(GoogleEventDateTime::local(start)).unwrap(),
+            serde_json::json!({"dateTime": "2024-05-02T19:00:00", "timeZone": "Europe/Zurich"})
+        );
+    }
+}
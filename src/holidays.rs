@@ -0,0 +1,381 @@
+use crate::{error::AutospielerError, read_optional_env};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// Easter Sunday of `year` in the Gregorian calendar (anonymous Gregorian algorithm).
+pub fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+enum Day {
+    Fixed(u32, u32),
+    /// Days after Easter Sunday.
+    Easter(i64),
+    Computed(fn(i32) -> NaiveDate),
+}
+
+enum Regions {
+    All,
+    Only(&'static [&'static str]),
+    Except(&'static [&'static str]),
+}
+
+struct Holiday {
+    name: &'static str,
+    day: Day,
+    regions: Regions,
+    /// First year the holiday is observed.
+    since: i32,
+}
+
+impl Holiday {
+    const fn new(name: &'static str, day: Day, regions: Regions) -> Self {
+        Holiday {
+            name,
+            day,
+            regions,
+            since: i32::MIN,
+        }
+    }
+
+    const fn since(self, year: i32) -> Self {
+        Holiday {
+            since: year,
+            ..self
+        }
+    }
+
+    fn date(&self, year: i32) -> Option<NaiveDate> {
+        match self.day {
+            Day::Fixed(month, day) => NaiveDate::from_ymd_opt(year, month, day),
+            Day::Easter(offset) => Some(easter_sunday(year) + Duration::days(offset)),
+            Day::Computed(date) => Some(date(year)),
+        }
+    }
+
+    fn is_observed_in(&self, region: Option<&str>) -> bool {
+        match (&self.regions, region) {
+            (Regions::All, _) => true,
+            (Regions::Only(regions), Some(region)) => regions.contains(&region),
+            (Regions::Except(regions), Some(region)) => !regions.contains(&region),
+            // without a region, only holidays observed everywhere count
+            (_, None) => false,
+        }
+    }
+}
+
+/// The `n`th `weekday` of `month`, counting from 1.
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8).unwrap()
+}
+
+/// Jeûne genevois, the Thursday after the first Sunday of September.
+fn jeune_genevois(year: i32) -> NaiveDate {
+    nth_weekday(year, 9, Weekday::Sun, 1) + Duration::days(4)
+}
+
+/// Lundi du Jeûne, the Monday after the third Sunday of September.
+fn lundi_du_jeune(year: i32) -> NaiveDate {
+    nth_weekday(year, 9, Weekday::Sun, 3) + Duration::days(1)
+}
+
+/// Buß- und Bettag, the last Wednesday before November 23.
+fn buss_und_bettag(year: i32) -> NaiveDate {
+    let november_22 = NaiveDate::from_ymd_opt(year, 11, 22).unwrap();
+    let days_since_wednesday = (november_22.weekday().num_days_from_monday() + 7 - 2) % 7;
+    november_22 - Duration::days(days_since_wednesday.into())
+}
+
+const CANTONS: &[&str] = &[
+    "AG", "AI", "AR", "BE", "BL", "BS", "FR", "GE", "GL", "GR", "JU", "LU", "NE", "NW", "OW", "SG",
+    "SH", "SO", "SZ", "TG", "TI", "UR", "VD", "VS", "ZG", "ZH",
+];
+
+const SWISS_HOLIDAYS: &[Holiday] = &[
+    Holiday::new("Neujahrstag", Day::Fixed(1, 1), Regions::All),
+    Holiday::new(
+        "Berchtoldstag",
+        Day::Fixed(1, 2),
+        Regions::Only(&[
+            "AG", "BE", "FR", "GL", "JU", "LU", "NE", "OW", "SH", "SO", "TG", "VD", "ZG", "ZH",
+        ]),
+    ),
+    Holiday::new(
+        "Heilige Drei Könige",
+        Day::Fixed(1, 6),
+        Regions::Only(&["SZ", "TI", "UR"]),
+    ),
+    Holiday::new(
+        "Instauration de la République",
+        Day::Fixed(3, 1),
+        Regions::Only(&["NE"]),
+    ),
+    Holiday::new(
+        "Josefstag",
+        Day::Fixed(3, 19),
+        Regions::Only(&["NW", "SZ", "TI", "UR", "VS"]),
+    ),
+    Holiday::new(
+        "Karfreitag",
+        Day::Easter(-2),
+        Regions::Except(&["TI", "VS"]),
+    ),
+    Holiday::new("Ostermontag", Day::Easter(1), Regions::Except(&["VS"])),
+    Holiday::new(
+        "Tag der Arbeit",
+        Day::Fixed(5, 1),
+        Regions::Only(&["BL", "BS", "JU", "NE", "SH", "SO", "TG", "TI", "ZH"]),
+    ),
+    Holiday::new("Auffahrt", Day::Easter(39), Regions::All),
+    Holiday::new("Pfingstmontag", Day::Easter(50), Regions::Except(&["VS"])),
+    Holiday::new(
+        "Fronleichnam",
+        Day::Easter(60),
+        Regions::Only(&[
+            "AI", "FR", "JU", "LU", "NW", "OW", "SZ", "TI", "UR", "VS", "ZG",
+        ]),
+    ),
+    Holiday::new(
+        "Commémoration du plébiscite",
+        Day::Fixed(6, 23),
+        Regions::Only(&["JU"]),
+    ),
+    Holiday::new("Peter und Paul", Day::Fixed(6, 29), Regions::Only(&["TI"])),
+    Holiday::new("Bundesfeier", Day::Fixed(8, 1), Regions::All),
+    Holiday::new(
+        "Mariä Himmelfahrt",
+        Day::Fixed(8, 15),
+        Regions::Only(&[
+            "AI", "FR", "JU", "LU", "NW", "OW", "SZ", "TI", "UR", "VS", "ZG",
+        ]),
+    ),
+    Holiday::new(
+        "Jeûne genevois",
+        Day::Computed(jeune_genevois),
+        Regions::Only(&["GE"]),
+    ),
+    Holiday::new(
+        "Lundi du Jeûne",
+        Day::Computed(lundi_du_jeune),
+        Regions::Only(&["VD"]),
+    ),
+    Holiday::new(
+        "Allerheiligen",
+        Day::Fixed(11, 1),
+        Regions::Only(&[
+            "AI", "FR", "GL", "JU", "LU", "NW", "OW", "SG", "SZ", "TI", "UR", "VS", "ZG",
+        ]),
+    ),
+    Holiday::new(
+        "Mariä Empfängnis",
+        Day::Fixed(12, 8),
+        Regions::Only(&["AI", "FR", "LU", "NW", "OW", "SZ", "TI", "UR", "VS", "ZG"]),
+    ),
+    Holiday::new("Weihnachtstag", Day::Fixed(12, 25), Regions::All),
+    Holiday::new(
+        "Stephanstag",
+        Day::Fixed(12, 26),
+        Regions::Except(&["GE", "JU", "NE", "VD", "VS"]),
+    ),
+    Holiday::new(
+        "Restauration de la République",
+        Day::Fixed(12, 31),
+        Regions::Only(&["GE"]),
+    ),
+];
+
+const STATES: &[&str] = &[
+    "BB", "BE", "BW", "BY", "HB", "HE", "HH", "MV", "NI", "NW", "RP", "SH", "SL", "SN", "ST", "TH",
+];
+
+const GERMAN_HOLIDAYS: &[Holiday] = &[
+    Holiday::new("Neujahr", Day::Fixed(1, 1), Regions::All),
+    Holiday::new(
+        "Heilige Drei Könige",
+        Day::Fixed(1, 6),
+        Regions::Only(&["BW", "BY", "ST"]),
+    ),
+    Holiday::new(
+        "Internationaler Frauentag",
+        Day::Fixed(3, 8),
+        Regions::Only(&["BE"]),
+    )
+    .since(2019),
+    Holiday::new(
+        "Internationaler Frauentag",
+        Day::Fixed(3, 8),
+        Regions::Only(&["MV"]),
+    )
+    .since(2023),
+    Holiday::new("Karfreitag", Day::Easter(-2), Regions::All),
+    Holiday::new("Ostersonntag", Day::Easter(0), Regions::Only(&["BB"])),
+    Holiday::new("Ostermontag", Day::Easter(1), Regions::All),
+    Holiday::new("Tag der Arbeit", Day::Fixed(5, 1), Regions::All),
+    Holiday::new("Christi Himmelfahrt", Day::Easter(39), Regions::All),
+    Holiday::new("Pfingstsonntag", Day::Easter(49), Regions::Only(&["BB"])),
+    Holiday::new("Pfingstmontag", Day::Easter(50), Regions::All),
+    Holiday::new(
+        "Fronleichnam",
+        Day::Easter(60),
+        Regions::Only(&["BW", "BY", "HE", "NW", "RP", "SL"]),
+    ),
+    Holiday::new(
+        "Mariä Himmelfahrt",
+        Day::Fixed(8, 15),
+        Regions::Only(&["SL"]),
+    ),
+    Holiday::new("Weltkindertag", Day::Fixed(9, 20), Regions::Only(&["TH"])).since(2019),
+    Holiday::new("Tag der Deutschen Einheit", Day::Fixed(10, 3), Regions::All).since(1990),
+    Holiday::new(
+        "Reformationstag",
+        Day::Fixed(10, 31),
+        Regions::Only(&["BB", "MV", "SN", "ST", "TH"]),
+    ),
+    Holiday::new(
+        "Reformationstag",
+        Day::Fixed(10, 31),
+        Regions::Only(&["HB", "HH", "NI", "SH"]),
+    )
+    .since(2018),
+    Holiday::new(
+        "Allerheiligen",
+        Day::Fixed(11, 1),
+        Regions::Only(&["BW", "BY", "NW", "RP", "SL"]),
+    ),
+    Holiday::new(
+        "Buß- und Bettag",
+        Day::Computed(buss_und_bettag),
+        Regions::Only(&["SN"]),
+    ),
+    Holiday::new("1. Weihnachtstag", Day::Fixed(12, 25), Regions::All),
+    Holiday::new("2. Weihnachtstag", Day::Fixed(12, 26), Regions::All),
+];
+
+/// The public holidays of a country or one of its cantons or states, from
+/// `PUBLIC_HOLIDAYS`, e.g. `CH-ZH`, `DE-BY` or just `CH` for the holidays observed in
+/// the whole country.
+pub struct HolidayCalendar {
+    holidays: &'static [Holiday],
+    region: Option<String>,
+}
+
+impl HolidayCalendar {
+    pub fn from_env() -> Result<Option<Self>, AutospielerError> {
+        let Some(code) = read_optional_env("PUBLIC_HOLIDAYS")? else {
+            return Ok(None);
+        };
+        let code = code.trim().to_uppercase();
+        let (country, region) = match code.split_once('-') {
+            Some((country, region)) => (country, Some(region)),
+            None => (code.as_str(), None),
+        };
+
+        let (holidays, regions) = match country {
+            "CH" => (SWISS_HOLIDAYS, CANTONS),
+            "DE" => (GERMAN_HOLIDAYS, STATES),
+            _ => {
+                return Err(AutospielerError::config(format!(
+                    "unknown PUBLIC_HOLIDAYS country '{country}', expected 'CH' or 'DE'"
+                )))
+            }
+        };
+        if let Some(region) = region.filter(|region| !regions.contains(region)) {
+            return Err(AutospielerError::config(format!(
+                "unknown PUBLIC_HOLIDAYS region '{region}', expected one of {}",
+                regions.join(", ")
+            )));
+        }
+
+        Ok(Some(HolidayCalendar {
+            holidays,
+            region: region.map(str::to_owned),
+        }))
+    }
+
+    /// Name of the public holiday on `date`, `None` if it's a working day.
+    pub fn holiday(&self, date: NaiveDate) -> Option<&'static str> {
+        self.holidays
+            .iter()
+            .find(|holiday| {
+                date.year() >= holiday.since
+                    && holiday.is_observed_in(self.region.as_deref())
+                    && holiday.date(date.year()) == Some(date)
+            })
+            .map(|holiday| holiday.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn calendar(holidays: &'static [Holiday], region: Option<&str>) -> HolidayCalendar {
+        HolidayCalendar {
+            holidays,
+            region: region.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn computes_easter_sunday() {
+        assert_eq!(easter_sunday(2024), date(2024, 3, 31));
+        assert_eq!(easter_sunday(2025), date(2025, 4, 20));
+        assert_eq!(easter_sunday(2038), date(2038, 4, 25));
+    }
+
+    #[test]
+    fn finds_cantonal_holidays() {
+        let zurich = calendar(SWISS_HOLIDAYS, Some("ZH"));
+        assert_eq!(zurich.holiday(date(2025, 1, 2)), Some("Berchtoldstag"));
+        assert_eq!(zurich.holiday(date(2024, 4, 1)), Some("Ostermontag"));
+        assert_eq!(zurich.holiday(date(2024, 5, 30)), None);
+
+        let schwyz = calendar(SWISS_HOLIDAYS, Some("SZ"));
+        assert_eq!(schwyz.holiday(date(2025, 1, 2)), None);
+        assert_eq!(schwyz.holiday(date(2024, 5, 30)), Some("Fronleichnam"));
+
+        // without a canton, only the holidays of the whole country count
+        let switzerland = calendar(SWISS_HOLIDAYS, None);
+        assert_eq!(switzerland.holiday(date(2025, 1, 2)), None);
+        assert_eq!(switzerland.holiday(date(2025, 8, 1)), Some("Bundesfeier"));
+    }
+
+    #[test]
+    fn finds_state_holidays() {
+        // Fronleichnam is 60 days after Easter Sunday
+        let bavaria = calendar(GERMAN_HOLIDAYS, Some("BY"));
+        assert_eq!(bavaria.holiday(date(2024, 5, 30)), Some("Fronleichnam"));
+        assert_eq!(bavaria.holiday(date(2025, 6, 19)), Some("Fronleichnam"));
+
+        let berlin = calendar(GERMAN_HOLIDAYS, Some("BE"));
+        assert_eq!(berlin.holiday(date(2024, 5, 30)), None);
+        assert_eq!(
+            berlin.holiday(date(2024, 10, 3)),
+            Some("Tag der Deutschen Einheit")
+        );
+    }
+
+    #[test]
+    fn observes_holidays_from_their_first_year() {
+        let hamburg = calendar(GERMAN_HOLIDAYS, Some("HH"));
+        assert_eq!(hamburg.holiday(date(2017, 10, 31)), None);
+        assert_eq!(hamburg.holiday(date(2018, 10, 31)), Some("Reformationstag"));
+    }
+}
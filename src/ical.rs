@@ -0,0 +1,468 @@
+use crate::{
+    calendar::{Attendance, NewCalendarEvent},
+    local_time::utc_to_local,
+};
+use chrono::{NaiveDateTime, Utc};
+
+/// Holds the Spielerplus id of the event, like the `SP_ID` property in Outlook.
+pub const SP_ID_PROPERTY: &str = "X-SPIELERPLUS-ID";
+
+/// Time zone of all events Autospieler writes, Spielerplus shows local Swiss times.
+pub const TIME_ZONE: &str = "Europe/Zurich";
+
+const VTIMEZONE: &str = "BEGIN:VTIMEZONE\r
+TZID:Europe/Zurich\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+TZNAME:CEST\r
+DTSTART:19700329T020000\r
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+TZNAME:CET\r
+DTSTART:19701025T030000\r
+RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r
+END:STANDARD\r
+END:VTIMEZONE\r
+";
+
+/// Lines are folded after this many octets, see RFC 5545 section 3.1.
+const MAX_LINE_LENGTH: usize = 75;
+
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn new(name: &str, value: &str) -> Self {
+        Property {
+            name: name.to_owned(),
+            params: Vec::new(),
+            value: value.to_owned(),
+        }
+    }
+
+    /// A `TEXT` property, escaping the value.
+    pub fn text(name: &str, value: &str) -> Self {
+        Property::new(name, &escape_text(value))
+    }
+
+    /// A local date-time in [`TIME_ZONE`].
+    pub fn local_date_time(name: &str, value: NaiveDateTime) -> Self {
+        Property::new(name, &value.format("%Y%m%dT%H%M%S").to_string())
+            .with_param("TZID", TIME_ZONE)
+    }
+
+    /// The current time in UTC, e.g. for `DTSTAMP`.
+    pub fn now(name: &str) -> Self {
+        Property::new(name, &Utc::now().format("%Y%m%dT%H%M%SZ").to_string())
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn text_value(&self) -> String {
+        unescape_text(&self.value)
+    }
+
+    /// Reads a `DATE-TIME` value as local time. UTC values are converted to
+    /// [`TIME_ZONE`], values with a `TZID` are taken as they are.
+    pub fn date_time_value(&self) -> Option<NaiveDateTime> {
+        match self.value.strip_suffix('Z') {
+            Some(utc) => {
+                let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+                Some(utc_to_local(utc))
+            }
+            None => NaiveDateTime::parse_from_str(&self.value, "%Y%m%dT%H%M%S").ok(),
+        }
+    }
+
+    /// Parses an unfolded content line.
+    fn parse(line: &str) -> Option<Self> {
+        let mut name_end = None;
+        let mut in_quotes = false;
+        for (index, char) in line.char_indices() {
+            match char {
+                '"' => in_quotes = !in_quotes,
+                ':' if !in_quotes => {
+                    name_end = Some(index);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let name_end = name_end?;
+        let (head, value) = (&line[..name_end], &line[name_end + 1..]);
+
+        let mut parts = split_unquoted(head, ';').into_iter();
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                Some((
+                    name.to_ascii_uppercase(),
+                    value.trim_matches('"').to_owned(),
+                ))
+            })
+            .collect();
+
+        Some(Property {
+            name,
+            params,
+            value: value.to_owned(),
+        })
+    }
+
+    fn write(&self, output: &mut String) {
+        let mut line = self.name.clone();
+        for (name, value) in &self.params {
+            if value.contains([':', ';', ',']) {
+                line.push_str(&format!(";{name}=\"{value}\""));
+            } else {
+                line.push_str(&format!(";{name}={value}"));
+            }
+        }
+        line.push(':');
+        line.push_str(&self.value);
+        fold(&line, output);
+    }
+}
+
+fn split_unquoted(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    for (index, char) in input.char_indices() {
+        match char {
+            '"' => in_quotes = !in_quotes,
+            char if char == separator && !in_quotes => {
+                parts.push(&input[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+fn fold(line: &str, output: &mut String) {
+    let mut length = 0;
+    for char in line.chars() {
+        if length + char.len_utf8() > MAX_LINE_LENGTH {
+            output.push_str("\r\n ");
+            length = 1;
+        }
+        output.push(char);
+        length += char.len_utf8();
+    }
+    output.push_str("\r\n");
+}
+
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+pub fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+pub fn unescape_text(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            output.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => output.push('\n'),
+            Some(escaped) => output.push(escaped),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+/// A component like `VCALENDAR` or `VEVENT`. Properties and subcomponents are kept in
+/// their original order, so unknown ones survive reading and writing a component.
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    pub fn new(name: &str) -> Self {
+        Component {
+            name: name.to_owned(),
+            properties: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+
+    /// A `VCALENDAR` with the time zone definition used by [`Property::local_date_time`].
+    pub fn calendar(method: Option<&str>) -> Self {
+        let mut calendar = Component::new("VCALENDAR");
+        calendar.push(Property::new("VERSION", "2.0"));
+        calendar.push(Property::new("PRODID", "-//Autospieler//Autospieler//EN"));
+        if let Some(method) = method {
+            calendar.push(Property::new("METHOD", method));
+        }
+        calendar.components.extend(Component::parse(VTIMEZONE));
+        calendar
+    }
+
+    /// Parses the first component in `text`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut stack: Vec<Component> = Vec::new();
+        for line in unfold(text) {
+            let property = Property::parse(&line)?;
+            match property.name.as_str() {
+                "BEGIN" => stack.push(Component::new(&property.value.to_ascii_uppercase())),
+                "END" => {
+                    let component = stack.pop()?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.components.push(component),
+                        None => return Some(component),
+                    }
+                }
+                _ => stack.last_mut()?.properties.push(property),
+            }
+        }
+        None
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    pub fn properties<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> {
+        self.properties
+            .iter()
+            .filter(move |property| property.name == name)
+    }
+
+    pub fn push(&mut self, property: Property) {
+        self.properties.push(property);
+    }
+
+    /// Replaces all properties called like `property`.
+    pub fn set(&mut self, property: Property) {
+        let index = self.properties.iter().position(|p| p.name == property.name);
+        self.properties.retain(|p| p.name != property.name);
+        match index {
+            Some(index) => self.properties.insert(index, property),
+            None => self.properties.push(property),
+        }
+    }
+
+    pub fn components<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Component> {
+        self.components
+            .iter()
+            .filter(move |component| component.name == name)
+    }
+
+    pub fn components_mut<'a>(
+        &'a mut self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a mut Component> {
+        self.components
+            .iter_mut()
+            .filter(move |component| component.name == name)
+    }
+
+    fn write(&self, output: &mut String) {
+        fold(&format!("BEGIN:{}", self.name), output);
+        for property in &self.properties {
+            property.write(output);
+        }
+        for component in &self.components {
+            component.write(output);
+        }
+        fold(&format!("END:{}", self.name), output);
+    }
+
+    pub fn to_ical(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output);
+        output
+    }
+
+    /// A `VEVENT` for a Spielerplus event, without organizer and attendees.
+    pub fn event(uid: &str, event: &NewCalendarEvent) -> Self {
+        let mut vevent = Component::new("VEVENT");
+        vevent.push(Property::new("UID", uid));
+        vevent.push(Property::now("DTSTAMP"));
+        vevent.push(Property::new("SEQUENCE", "0"));
+        vevent.push(Property::local_date_time("DTSTART", event.start));
+        vevent.push(Property::local_date_time("DTEND", event.end));
+        vevent.push(Property::text("SUMMARY", &event.subject));
+        vevent.push(Property::text("DESCRIPTION", &event.body));
+        vevent.push(Property::text("LOCATION", &event.location));
+        vevent.push(Property::text(SP_ID_PROPERTY, &event.spielerplus_id));
+        vevent
+    }
+
+    /// Increments `SEQUENCE` and updates `DTSTAMP`, which tells calendar clients that
+    /// the event changed significantly, e.g. moved or got cancelled.
+    pub fn bump_sequence(&mut self) {
+        let sequence = self
+            .property("SEQUENCE")
+            .and_then(|sequence| sequence.value.parse::<u32>().ok())
+            .unwrap_or(0);
+        self.set(Property::new("SEQUENCE", &(sequence + 1).to_string()));
+        self.set(Property::now("DTSTAMP"));
+    }
+
+    /// Moves the event to `start`-`end`.
+    pub fn reschedule(&mut self, start: NaiveDateTime, end: NaiveDateTime) {
+        self.set(Property::local_date_time("DTSTART", start));
+        self.set(Property::local_date_time("DTEND", end));
+        self.bump_sequence();
+    }
+
+    /// The `ATTENDEE` with the mail address `email_address`.
+    pub fn attendee(&self, email_address: &str) -> Option<&Property> {
+        self.properties("ATTENDEE").find(|attendee| {
+            attendee
+                .value
+                .get(..7)
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+                && attendee.value[7..].eq_ignore_ascii_case(email_address)
+        })
+    }
+}
+
+/// Maps a `PARTSTAT` to an attendance, `None` if the attendee hasn't answered yet.
+pub fn attendance_from_partstat(partstat: Option<&str>) -> Option<Attendance> {
+    match partstat
+        .map(|partstat| partstat.to_ascii_uppercase())
+        .as_deref()
+    {
+        None | Some("NEEDS-ACTION") => None,
+        Some("ACCEPTED") => Some(Attendance::Accepted),
+        Some("DECLINED") => Some(Attendance::Declined),
+        _ => Some(Attendance::Unsure),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_long_lines_at_75_octets() {
+        // the umlauts take two octets and must not be split
+        let subject = format!("{}Ü{}", "x".repeat(66), "ä".repeat(60));
+        let mut vevent = Component::new("VEVENT");
+        vevent.push(Property::text("SUMMARY", &subject));
+
+        let ical = vevent.to_ical();
+        let lines: Vec<_> = ical.split_terminator("\r\n").collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert_eq!(lines[1].len(), 74);
+        assert!(lines[2].starts_with(" Ü"));
+        assert_eq!(lines[2].len(), 75);
+
+        let parsed = Component::parse(&ical).unwrap();
+        assert_eq!(parsed.property("SUMMARY").unwrap().text_value(), subject);
+    }
+
+    #[test]
+    fn escapes_text_values() {
+        let text = "Training; Halle 2, Eingang C\\Süd\nBitte pünktlich";
+
+        assert_eq!(
+            escape_text(text),
+            "Training\\; Halle 2\\, Eingang C\\\\Süd\\nBitte pünktlich"
+        );
+        assert_eq!(unescape_text(&escape_text(text)), text);
+        assert_eq!(unescape_text("Zeile 1\\NZeile 2\\"), "Zeile 1\nZeile 2\\");
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let text = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:spielerplus-42@autospieler\r
+DTSTART;TZID=Europe/Zurich:20240502T190000\r
+summary:Training\\, lang\r
+\tweilig\r
+ATTENDEE;CN=\"Muster, Max: Trainer\";PARTSTAT=TENTATIVE:mailto:max@example.org\r
+X-UNKNOWN;X-PARAM=1:bleibt\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let calendar = Component::parse(text).unwrap();
+        let vevent = calendar.components("VEVENT").next().unwrap();
+        assert_eq!(
+            vevent.property("SUMMARY").unwrap().text_value(),
+            "Training, langweilig"
+        );
+        let attendee = vevent.attendee("MAX@example.org").unwrap();
+        assert_eq!(attendee.param("cn"), Some("Muster, Max: Trainer"));
+        assert_eq!(
+            attendance_from_partstat(attendee.param("PARTSTAT")),
+            Some(Attendance::Unsure)
+        );
+        assert_eq!(
+            vevent.property("DTSTART").unwrap().date_time_value(),
+            NaiveDateTime::parse_from_str("2024-05-02 19:00", "%Y-%m-%d %H:%M").ok()
+        );
+
+        let written = calendar.to_ical();
+        assert!(written.contains("SUMMARY:Training\\, langweilig\r\n"));
+        assert!(written.contains("X-UNKNOWN;X-PARAM=1:bleibt\r\n"));
+        assert_eq!(Component::parse(&written).unwrap().to_ical(), written);
+        assert!(Component::parse("BEGIN:VEVENT\r\nSUMMARY:unterminated\r\n").is_none());
+    }
+
+    #[test]
+    fn bumps_the_sequence_when_rescheduling() {
+        let start = NaiveDateTime::parse_from_str("2024-05-02 19:00", "%Y-%m-%d %H:%M").unwrap();
+        let mut vevent = Component::new("VEVENT");
+        vevent.push(Property::new("SEQUENCE", "4"));
+
+        vevent.reschedule(start, start + chrono::Duration::hours(2));
+
+        assert_eq!(vevent.property("SEQUENCE").unwrap().value, "5");
+        assert_eq!(vevent.property("DTEND").unwrap().value, "20240502T210000");
+        assert_eq!(
+            vevent.property("DTEND").unwrap().param("TZID"),
+            Some(TIME_ZONE)
+        );
+        assert!(vevent.property("DTSTAMP").is_some());
+    }
+}
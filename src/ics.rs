@@ -0,0 +1,421 @@
+use crate::{
+    calendar::{
+        Attendance, CalendarBackend, CalendarChange, CalendarEvent, CalendarEventMap,
+        NewCalendarEvent,
+    },
+    error::AutospielerError,
+    ical::{Component, Property, SP_ID_PROPERTY},
+    read_optional_env,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+};
+use tiny_http::{Header, Method, Response, Server};
+
+pub struct IcsFeedConfig {
+    pub path: PathBuf,
+    /// Address of the built-in HTTP endpoint serving the feed, e.g. `0.0.0.0:8081`.
+    pub listen_address: Option<String>,
+}
+
+impl IcsFeedConfig {
+    pub fn from_env(state_dir: &Path) -> Result<Self, AutospielerError> {
+        Ok(IcsFeedConfig {
+            path: read_optional_env("ICS_FEED_PATH")?
+                .map(PathBuf::from)
+                .unwrap_or_else(|| state_dir.join("spielerplus.ics")),
+            listen_address: read_optional_env("ICS_LISTEN_ADDRESS")?,
+        })
+    }
+}
+
+/// Stable UID of the feed event for a Spielerplus event.
+fn uid(spielerplus_id: &str) -> String {
+    format!("spielerplus-{spielerplus_id}@autospieler")
+}
+
+/// Read-only [`CalendarBackend`] that keeps all events in one `.ics` file, which
+/// calendar clients can subscribe to. Removed events stay in the feed with
+/// `STATUS:CANCELLED` until they're over, so subscribers drop them as well.
+pub struct IcsFeedBackend {
+    path: PathBuf,
+    calendar: Component,
+}
+
+impl IcsFeedBackend {
+    pub fn new(path: PathBuf) -> Result<Self, AutospielerError> {
+        let calendar = match fs::read_to_string(&path) {
+            Ok(text) => Component::parse(&text).ok_or_else(|| {
+                AutospielerError::Calendar(format!("{} is not valid iCalendar", path.display()))
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let mut calendar = Component::calendar(None);
+                calendar.push(Property::text("X-WR-CALNAME", "Spielerplus"));
+                calendar
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(IcsFeedBackend { path, calendar })
+    }
+
+    fn save(&self) -> Result<(), AutospielerError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // write to a temporary file first, so the endpoint never serves half a feed
+        let temporary_path = self.path.with_extension("ics.tmp");
+        fs::write(&temporary_path, self.calendar.to_ical())?;
+        fs::rename(&temporary_path, &self.path)?;
+        Ok(())
+    }
+
+    fn find_event(&mut self, event_id: &str) -> Option<&mut Component> {
+        self.calendar.components_mut("VEVENT").find(|vevent| {
+            vevent
+                .property("UID")
+                .is_some_and(|uid| uid.value == event_id)
+        })
+    }
+
+    fn add_event(&mut self, event: &NewCalendarEvent) {
+        let event_id = uid(&event.spielerplus_id);
+        match self.find_event(&event_id) {
+            // the event was cancelled before and showed up in Spielerplus again
+            Some(vevent) => {
+                vevent.reschedule(event.start, event.end);
+                vevent.set(Property::new("STATUS", "CONFIRMED"));
+            }
+            None => {
+                let mut vevent = Component::event(&event_id, event);
+                vevent.push(Property::new("STATUS", "CONFIRMED"));
+                self.calendar.components.push(vevent);
+            }
+        }
+    }
+
+    fn reschedule_event(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        self.find_event(event_id)
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?
+            .reschedule(start, end);
+        Ok(())
+    }
+
+    fn rename_event(&mut self, event_id: &str, subject: &str) -> Result<(), AutospielerError> {
+        self.find_event(event_id)
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?
+            .set(Property::text("SUMMARY", subject));
+        Ok(())
+    }
+
+    fn describe_event(&mut self, event_id: &str, body: &str) -> Result<(), AutospielerError> {
+        self.find_event(event_id)
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?
+            .set(Property::text("DESCRIPTION", body));
+        Ok(())
+    }
+
+    fn mark_cancelled(&mut self, event_id: &str) {
+        if let Some(vevent) = self.find_event(event_id) {
+            vevent.set(Property::new("STATUS", "CANCELLED"));
+            vevent.bump_sequence();
+        }
+    }
+
+    /// Drops the cancelled events that ended before `current_date`, subscribers don't
+    /// need to be told about them anymore. Returns whether any were dropped.
+    fn forget_cancelled_before(&mut self, current_date: NaiveDate) -> bool {
+        let count = self.calendar.components.len();
+        self.calendar.components.retain(|component| {
+            let is_over = component
+                .property("DTEND")
+                .and_then(Property::date_time_value)
+                .is_some_and(|end| end.date() < current_date);
+            !(component.name == "VEVENT" && is_cancelled(component) && is_over)
+        });
+        self.calendar.components.len() != count
+    }
+}
+
+fn is_cancelled(vevent: &Component) -> bool {
+    vevent
+        .property("STATUS")
+        .is_some_and(|status| status.value == "CANCELLED")
+}
+
+fn to_calendar_event(vevent: &Component) -> Option<CalendarEvent> {
+    if is_cancelled(vevent) {
+        return None;
+    }
+
+    Some(CalendarEvent {
+        id: vevent.property("UID")?.value.clone(),
+        spielerplus_id: vevent.property(SP_ID_PROPERTY)?.text_value(),
+        subject: vevent
+            .property("SUMMARY")
+            .map(Property::text_value)
+            .unwrap_or_default(),
+        start: vevent.property("DTSTART")?.date_time_value()?,
+        end: vevent.property("DTEND")?.date_time_value()?,
+        // nobody answers a read-only feed
+        attendance: None,
+        response_time: None,
+    })
+}
+
+impl CalendarBackend for IcsFeedBackend {
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        if self.forget_cancelled_before(current_date) {
+            self.save()?;
+        }
+
+        Ok(self
+            .calendar
+            .components("VEVENT")
+            .filter_map(to_calendar_event)
+            .filter(|event| event.start.date() >= current_date)
+            .map(|event| (event.spielerplus_id.clone(), event))
+            .collect())
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        Ok(self
+            .find_event(event_id)
+            .and_then(|vevent| to_calendar_event(vevent)))
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        self.add_event(event);
+        self.save()
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        self.reschedule_event(event_id, start, end)?;
+        self.save()
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        self.mark_cancelled(event_id);
+        self.save()
+    }
+
+    fn show_spielerplus_attendance(
+        &mut self,
+        event_id: &str,
+        subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        self.rename_event(event_id, subject)?;
+        self.save()
+    }
+
+    fn update_event_body(&mut self, event_id: &str, body: &str) -> Result<(), AutospielerError> {
+        self.describe_event(event_id, body)?;
+        self.save()
+    }
+
+    /// Applies all changes and writes the feed once.
+    fn apply_changes(&mut self, changes: &[CalendarChange]) -> Vec<Result<(), AutospielerError>> {
+        let mut results: Vec<_> = changes
+            .iter()
+            .map(|change| match change {
+                CalendarChange::Create(event) => {
+                    self.add_event(event);
+                    Ok(())
+                }
+                CalendarChange::UpdateTime {
+                    event_id,
+                    start,
+                    end,
+                } => self.reschedule_event(event_id, *start, *end),
+                CalendarChange::Cancel { event_id } => {
+                    self.mark_cancelled(event_id);
+                    Ok(())
+                }
+                CalendarChange::ShowSpielerplusAttendance {
+                    event_id, subject, ..
+                } => self.rename_event(event_id, subject),
+                CalendarChange::UpdateBody { event_id, body } => {
+                    self.describe_event(event_id, body)
+                }
+            })
+            .collect();
+
+        if let Err(err) = self.save() {
+            let message = err.to_string();
+            for result in results.iter_mut().filter(|result| result.is_ok()) {
+                *result = Err(AutospielerError::Calendar(format!(
+                    "could not write the feed: {message}"
+                )));
+            }
+        }
+
+        results
+    }
+}
+
+/// Serves the feed at `path` on `listen_address` from a background thread. Every
+/// request gets the file as it currently is on disk.
+pub fn start_feed_server(listen_address: &str, path: PathBuf) -> Result<(), AutospielerError> {
+    let server = Server::http(listen_address).map_err(|e| {
+        AutospielerError::config(format!("could not listen on {listen_address}: {e}"))
+    })?;
+
+    println!("Serving the ICS feed on {listen_address}");
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match (request.method(), fs::read(&path)) {
+                (Method::Get | Method::Head, Ok(feed)) => {
+                    let content_type =
+                        Header::from_bytes("Content-Type", "text/calendar; charset=utf-8").unwrap();
+                    Response::from_data(feed).with_header(content_type)
+                }
+                (Method::Get | Method::Head, Err(_)) => {
+                    Response::from_data(Vec::new()).with_status_code(404)
+                }
+                _ => Response::from_data(Vec::new()).with_status_code(405),
+            };
+
+            if let Err(err) = request.respond(response) {
+                eprintln!("could not serve the ICS feed: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn new_event(spielerplus_id: &str, start: &str) -> NewCalendarEvent {
+        NewCalendarEvent {
+            subject: "Training".to_owned(),
+            body: String::new(),
+            start: at(start),
+            end: at(start) + chrono::Duration::hours(2),
+            location: "Halle 2".to_owned(),
+            spielerplus_id: spielerplus_id.to_owned(),
+            first_attempt: None,
+        }
+    }
+
+    fn feed_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "autospieler-test-{}-{name}.ics",
+            std::process::id()
+        ))
+    }
+
+    fn vevent<'a>(feed: &'a Component, spielerplus_id: &str) -> Option<&'a Component> {
+        feed.components("VEVENT").find(|vevent| {
+            vevent
+                .property("UID")
+                .is_some_and(|property| property.value == uid(spielerplus_id))
+        })
+    }
+
+    fn property(feed: &Component, spielerplus_id: &str, name: &str) -> String {
+        vevent(feed, spielerplus_id)
+            .unwrap()
+            .property(name)
+            .unwrap()
+            .value
+            .clone()
+    }
+
+    #[test]
+    fn keeps_events_with_their_uid_and_sequence() {
+        let path = feed_path("sequence");
+        let mut backend = IcsFeedBackend::new(path.clone()).unwrap();
+        let event_id = uid("42");
+
+        backend
+            .create_event(&new_event("42", "2024-05-02 19:00"))
+            .unwrap();
+        backend
+            .update_event_time(&event_id, at("2024-05-02 20:00"), at("2024-05-02 22:00"))
+            .unwrap();
+
+        let feed = Component::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(property(&feed, "42", "SEQUENCE"), "1");
+        assert_eq!(property(&feed, "42", "DTSTART"), "20240502T200000");
+        assert_eq!(property(&feed, "42", "STATUS"), "CONFIRMED");
+        let events = backend.list_events(at("2024-05-01 00:00").date()).unwrap();
+        assert_eq!(events["42"].id, event_id);
+
+        backend.cancel_event(&event_id).unwrap();
+        let feed = Component::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(property(&feed, "42", "STATUS"), "CANCELLED");
+        assert_eq!(property(&feed, "42", "SEQUENCE"), "2");
+        assert!(backend
+            .list_events(at("2024-05-01 00:00").date())
+            .unwrap()
+            .is_empty());
+        assert!(backend.get_event(&event_id).unwrap().is_none());
+
+        // showing up in Spielerplus again revives the cancelled event
+        backend
+            .create_event(&new_event("42", "2024-05-02 19:00"))
+            .unwrap();
+        let feed = Component::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(feed.components("VEVENT").count(), 1);
+        assert_eq!(property(&feed, "42", "STATUS"), "CONFIRMED");
+        assert_eq!(property(&feed, "42", "SEQUENCE"), "3");
+    }
+
+    #[test]
+    fn forgets_cancelled_events_once_they_are_over() {
+        let path = feed_path("forget");
+        let mut backend = IcsFeedBackend::new(path.clone()).unwrap();
+        let results = backend.apply_changes(&[
+            CalendarChange::Create(new_event("42", "2024-05-02 19:00")),
+            CalendarChange::Create(new_event("43", "2024-05-09 19:00")),
+            CalendarChange::Create(new_event("44", "2024-05-02 18:00")),
+        ]);
+        assert!(results.iter().all(Result::is_ok));
+        backend.cancel_event(&uid("42")).unwrap();
+        backend.cancel_event(&uid("43")).unwrap();
+
+        backend.list_events(at("2024-05-02 00:00").date()).unwrap();
+        let feed = Component::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(vevent(&feed, "42").is_some());
+
+        let events = backend.list_events(at("2024-05-03 00:00").date()).unwrap();
+        let feed = Component::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(events.is_empty());
+        assert!(vevent(&feed, "42").is_none());
+        // still cancelled in the future, and past events that took place stay
+        assert_eq!(property(&feed, "43", "STATUS"), "CANCELLED");
+        assert_eq!(property(&feed, "44", "STATUS"), "CONFIRMED");
+        assert!(feed.components("VTIMEZONE").next().is_some());
+    }
+}
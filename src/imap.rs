@@ -0,0 +1,286 @@
+use crate::error::AutospielerError;
+use native_tls::TlsConnector;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+fn imap_error(message: impl std::fmt::Display) -> AutospielerError {
+    AutospielerError::Calendar(format!("IMAP: {message}"))
+}
+
+/// Quotes a string argument, see RFC 3501 section 4.3.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads the `{n}` at the end of a response line, announcing a literal of `n` octets.
+fn literal_length(line: &str) -> Option<usize> {
+    line.trim_end()
+        .strip_suffix('}')?
+        .rsplit_once('{')?
+        .1
+        .parse()
+        .ok()
+}
+
+/// An untagged response. `text` holds its lines with the literals cut out, so a literal
+/// is announced by the `{n}` in `text` and the items around it read as one line.
+struct Response {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+impl Response {
+    /// The literal announced right after `item`, e.g. the message of `BODY[]`.
+    fn literal_after(&self, item: &str) -> Option<&[u8]> {
+        let position = self.text.find(&format!("{item} {{"))?;
+        let index = self.text[..position].matches("} ").count();
+        self.literals.get(index).map(Vec::as_slice)
+    }
+
+    /// The value of an item like `UID 17`, wherever the server put it.
+    fn number(&self, item: &str) -> Option<u32> {
+        let mut words = self.text.split([' ', '(', ')']);
+        words.find(|word| *word == item)?;
+        words.next()?.parse().ok()
+    }
+}
+
+/// Just enough IMAP4rev1 to fetch new messages from one mailbox.
+pub struct ImapSession {
+    stream: BufReader<Box<dyn Stream>>,
+    next_tag: u32,
+}
+
+impl ImapSession {
+    pub fn connect(host: &str, port: u16, tls: bool) -> Result<Self, AutospielerError> {
+        let tcp = TcpStream::connect((host, port)).map_err(imap_error)?;
+        tcp.set_read_timeout(Some(TIMEOUT)).map_err(imap_error)?;
+        tcp.set_write_timeout(Some(TIMEOUT)).map_err(imap_error)?;
+
+        let stream: Box<dyn Stream> = if tls {
+            let connector = TlsConnector::new().map_err(imap_error)?;
+            Box::new(connector.connect(host, tcp).map_err(imap_error)?)
+        } else {
+            Box::new(tcp)
+        };
+
+        let mut session = ImapSession {
+            stream: BufReader::new(stream),
+            next_tag: 0,
+        };
+        let greeting = session.read_line()?;
+        if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+            return Err(imap_error(format!(
+                "unexpected greeting {}",
+                greeting.trim()
+            )));
+        }
+        Ok(session)
+    }
+
+    fn read_line(&mut self) -> Result<String, AutospielerError> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).map_err(imap_error)? == 0 {
+            return Err(imap_error("connection closed by the server"));
+        }
+        Ok(line)
+    }
+
+    /// Sends a command and collects the untagged responses.
+    fn command(&mut self, command: &str) -> Result<Vec<Response>, AutospielerError> {
+        self.next_tag += 1;
+        let tag = format!("A{:03}", self.next_tag);
+        write!(self.stream.get_mut(), "{tag} {command}\r\n").map_err(imap_error)?;
+        self.stream.get_mut().flush().map_err(imap_error)?;
+
+        let mut responses = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if let Some(status) = line.strip_prefix(&format!("{tag} ")) {
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                let verb = command.split(' ').next().unwrap_or(command);
+                return Err(imap_error(format!("{verb} failed: {}", status.trim())));
+            }
+
+            responses.push(self.read_literals(line)?);
+        }
+    }
+
+    /// Reads the literals announced by `line` and the rest of the response after each.
+    fn read_literals(&mut self, mut line: String) -> Result<Response, AutospielerError> {
+        let mut response = Response {
+            text: String::new(),
+            literals: Vec::new(),
+        };
+        while let Some(length) = literal_length(&line) {
+            response.text.push_str(line.trim_end());
+            response.text.push(' ');
+            let mut literal = vec![0; length];
+            self.stream.read_exact(&mut literal).map_err(imap_error)?;
+            response.literals.push(literal);
+            // the rest of the response, e.g. ` UID 17)` or just the closing parenthesis
+            line = self.read_line()?;
+        }
+        response.text.push_str(line.trim());
+        Ok(response)
+    }
+
+    pub fn login(&mut self, username: &str, password: &str) -> Result<(), AutospielerError> {
+        self.command(&format!("LOGIN {} {}", quote(username), quote(password)))
+            .map(|_| ())
+    }
+
+    /// Selects `mailbox` and returns its `UIDVALIDITY`.
+    pub fn select(&mut self, mailbox: &str) -> Result<u32, AutospielerError> {
+        let responses = self.command(&format!("SELECT {}", quote(mailbox)))?;
+        responses
+            .iter()
+            .find_map(|response| {
+                let rest = response.text.split("[UIDVALIDITY ").nth(1)?;
+                rest.split(']').next()?.trim().parse().ok()
+            })
+            .ok_or_else(|| imap_error(format!("no UIDVALIDITY for mailbox {mailbox}")))
+    }
+
+    /// Fetches the raw messages with a UID above `last_uid`, without marking them as seen.
+    pub fn fetch_after(&mut self, last_uid: u32) -> Result<Vec<(u32, Vec<u8>)>, AutospielerError> {
+        let responses = self.command(&format!("UID FETCH {}:* (UID BODY.PEEK[])", last_uid + 1))?;
+
+        Ok(responses
+            .into_iter()
+            .filter(|response| response.text.contains(" FETCH "))
+            .filter_map(|response| {
+                let uid = response.number("UID");
+                let message = response.literal_after("BODY[]");
+                if uid.is_none() || message.is_none() {
+                    eprintln!("Ignoring unreadable IMAP response {}", response.text);
+                }
+                Some((uid?, message?.to_vec()))
+            })
+            // `n:*` always includes the last message, even if its UID is below n
+            .filter(|(uid, _)| *uid > last_uid)
+            .collect())
+    }
+
+    pub fn logout(mut self) -> Result<(), AutospielerError> {
+        self.command("LOGOUT").map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Replays recorded server output and ignores the commands.
+    struct Transcript(Cursor<Vec<u8>>);
+
+    impl Read for Transcript {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Transcript {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn session(server: &str) -> ImapSession {
+        let transcript = Transcript(Cursor::new(server.as_bytes().to_vec()));
+        ImapSession {
+            stream: BufReader::new(Box::new(transcript)),
+            next_tag: 0,
+        }
+    }
+
+    #[test]
+    fn reads_literal_lengths() {
+        assert_eq!(
+            literal_length("* 3 FETCH (UID 17 BODY[] {342}\r\n"),
+            Some(342)
+        );
+        assert_eq!(literal_length("* 3 FETCH (BODY[] {0}"), Some(0));
+        assert_eq!(
+            literal_length("* 3 FETCH (UID 17 FLAGS (\\Seen))\r\n"),
+            None
+        );
+        assert_eq!(
+            literal_length("* OK [UIDVALIDITY 3857529045] UIDs valid\r\n"),
+            None
+        );
+        assert_eq!(literal_length("* 3 FETCH (BODY[] {abc}\r\n"), None);
+    }
+
+    #[test]
+    fn fetches_messages_from_a_server_transcript() {
+        // Dovecot puts the UID first, others after the literal, as RFC 3501 allows.
+        let mut session = session(concat!(
+            "* OK [CAPABILITY IMAP4rev1] Dovecot ready.\r\n",
+            "A001 OK [CAPABILITY IMAP4rev1] Logged in\r\n",
+            "* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n",
+            "* 3 EXISTS\r\n",
+            "* OK [UIDVALIDITY 1718020000] UIDs valid\r\n",
+            "* OK [UIDNEXT 18] Predicted next UID\r\n",
+            "A002 OK [READ-WRITE] Select completed (0.001 + 0.000 secs).\r\n",
+            "* 2 FETCH (UID 16 BODY[] {14}\r\n",
+            "Subject: one\r\n",
+            ")\r\n",
+            "* 3 FETCH (BODY[] {19}\r\n",
+            "Subject: two\r\n\r\n)\r\n",
+            " UID 17)\r\n",
+            "* 3 FETCH (FLAGS (\\Recent))\r\n",
+            "A003 OK Fetch completed (0.001 + 0.000 secs).\r\n",
+            "* BYE Logging out\r\n",
+            "A004 OK Logout completed.\r\n",
+        ));
+        assert!(session.read_line().unwrap().starts_with("* OK"));
+
+        session.login("user", "password").unwrap();
+        assert_eq!(session.select("INBOX").unwrap(), 1718020000);
+        let messages = session.fetch_after(15).unwrap();
+        session.logout().unwrap();
+
+        assert_eq!(
+            messages,
+            [
+                (16, b"Subject: one\r\n".to_vec()),
+                (17, b"Subject: two\r\n\r\n)\r\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_the_last_message_below_the_range() {
+        let mut session = session(concat!(
+            "* 3 FETCH (UID 17 BODY[] {5}\r\n",
+            "hello)\r\n",
+            "A001 OK Fetch completed.\r\n",
+        ));
+
+        assert!(session.fetch_after(17).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fails_on_a_rejected_command() {
+        let mut session = session("A001 NO [AUTHENTICATIONFAILED] Authentication failed.\r\n");
+
+        let err = session.login("user", "wrong").unwrap_err();
+        assert!(err.to_string().contains("LOGIN failed: NO"), "{err}");
+    }
+}
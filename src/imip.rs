@@ -0,0 +1,657 @@
+use crate::{
+    calendar::{Attendance, CalendarBackend, CalendarEvent, CalendarEventMap, NewCalendarEvent},
+    error::AutospielerError,
+    ical::{self, Component, Property},
+    imap::ImapSession,
+    read_env, read_optional_env, read_parsed_env,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use mail_parser::{MessageParser, MimeHeaders};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Namespace for the UIDs of sent invitations, so every Spielerplus event keeps its UID.
+const UID_NAMESPACE: uuid::Uuid = uuid::uuid!("6d1f4b7e-2a9c-4e0d-8f35-91b2c6e0a4d7");
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MailSecurity {
+    /// Upgrade a plain connection with `STARTTLS`.
+    StartTls,
+    /// TLS from the start, e.g. SMTP on port 465 or IMAP on port 993.
+    Tls,
+    None,
+}
+
+impl MailSecurity {
+    fn from_env(name: &str, default: MailSecurity) -> Result<Self, AutospielerError> {
+        match read_optional_env(name)?.as_deref() {
+            None => Ok(default),
+            Some("starttls") => Ok(MailSecurity::StartTls),
+            Some("tls") => Ok(MailSecurity::Tls),
+            Some("none") => Ok(MailSecurity::None),
+            Some(other) => Err(AutospielerError::config(format!(
+                "unknown {name} '{other}', expected 'starttls', 'tls' or 'none'"
+            ))),
+        }
+    }
+}
+
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub security: MailSecurity,
+}
+
+impl SmtpConfig {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let security = MailSecurity::from_env("SMTP_TLS", MailSecurity::StartTls)?;
+        let default_port = match security {
+            MailSecurity::Tls => 465,
+            MailSecurity::StartTls => 587,
+            MailSecurity::None => 25,
+        };
+        Ok(SmtpConfig {
+            host: read_env("SMTP_HOST")?,
+            port: read_parsed_env("SMTP_PORT", default_port)?,
+            username: read_optional_env("SMTP_USERNAME")?,
+            password: read_optional_env("SMTP_PASSWORD")?,
+            security,
+        })
+    }
+
+    pub fn transport(&self) -> Result<SmtpTransport, AutospielerError> {
+        let mut builder = match self.security {
+            MailSecurity::StartTls => {
+                SmtpTransport::starttls_relay(&self.host).map_err(smtp_error)?
+            }
+            MailSecurity::Tls => SmtpTransport::relay(&self.host).map_err(smtp_error)?,
+            MailSecurity::None => SmtpTransport::builder_dangerous(&self.host),
+        }
+        .port(self.port);
+        if let Some(username) = &self.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                self.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(builder.build())
+    }
+}
+
+pub struct ImapConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub tls: bool,
+    pub mailbox: String,
+}
+
+pub struct ImipConfig {
+    /// Sender of the invitations, replies of the attendee go to this address.
+    pub organizer_email: String,
+    pub smtp: SmtpConfig,
+    /// Mailbox of the organizer to read replies from, without it no responses are read.
+    pub imap: Option<ImapConfig>,
+    pub state_path: PathBuf,
+}
+
+impl ImipConfig {
+    pub fn from_env(state_dir: &Path) -> Result<Self, AutospielerError> {
+        let smtp = SmtpConfig::from_env()?;
+
+        let imap = match read_optional_env("IMAP_HOST")? {
+            Some(host) => {
+                let tls = match MailSecurity::from_env("IMAP_TLS", MailSecurity::Tls)? {
+                    MailSecurity::Tls => true,
+                    MailSecurity::None => false,
+                    MailSecurity::StartTls => {
+                        return Err(AutospielerError::config(
+                            "IMAP_TLS only supports 'tls' or 'none'",
+                        ))
+                    }
+                };
+                Some(ImapConfig {
+                    host,
+                    port: read_parsed_env("IMAP_PORT", if tls { 993 } else { 143 })?,
+                    username: read_env("IMAP_USERNAME")?,
+                    password: read_env("IMAP_PASSWORD")?,
+                    tls,
+                    mailbox: read_optional_env("IMAP_MAILBOX")?
+                        .unwrap_or_else(|| "INBOX".to_owned()),
+                })
+            }
+            None => None,
+        };
+
+        Ok(ImipConfig {
+            organizer_email: read_env("IMIP_ORGANIZER_EMAIL")?,
+            smtp,
+            imap,
+            state_path: state_dir.join("imip-state.json"),
+        })
+    }
+}
+
+/// An invitation that was sent and not cancelled yet.
+#[derive(Serialize, Deserialize, Clone)]
+struct SentEvent {
+    spielerplus_id: String,
+    subject: String,
+    body: String,
+    location: String,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    sequence: u32,
+    attendance: Option<Attendance>,
+}
+
+/// What's left of a cancelled invitation, so an invitation for the same event continues
+/// its sequence. Clients ignore invitations with a sequence below the cancellation.
+#[derive(Serialize, Deserialize, Clone)]
+struct CancelledEvent {
+    sequence: u32,
+    end: NaiveDateTime,
+}
+
+/// Mail has no server side calendar, so the sent invitations and the position in the
+/// mailbox are kept in the state directory.
+#[derive(Serialize, Deserialize, Default)]
+struct ImipState {
+    /// Sent invitations keyed by their UID.
+    events: HashMap<String, SentEvent>,
+    /// Cancelled invitations keyed by their UID, until their event is over.
+    #[serde(default)]
+    cancelled: HashMap<String, CancelledEvent>,
+    imap_uid_validity: Option<u32>,
+    imap_last_uid: u32,
+}
+
+impl ImipState {
+    /// The invitation to send for `event`. One that was sent before but not recorded as
+    /// changed is sent again, one that was cancelled comes back with a higher sequence.
+    fn invitation(&self, uid: &str, event: &NewCalendarEvent) -> SentEvent {
+        if let Some(sent_event) = self.events.get(uid) {
+            return sent_event.clone();
+        }
+        SentEvent {
+            spielerplus_id: event.spielerplus_id.clone(),
+            subject: event.subject.clone(),
+            body: event.body.clone(),
+            location: event.location.clone(),
+            start: event.start,
+            end: event.end,
+            sequence: self
+                .cancelled
+                .get(uid)
+                .map_or(0, |cancelled| cancelled.sequence + 1),
+            attendance: None,
+        }
+    }
+
+    fn invited(&mut self, uid: &str, event: SentEvent) {
+        self.cancelled.remove(uid);
+        self.events.insert(uid.to_owned(), event);
+    }
+
+    /// The cancellation to send for the invitation `uid`, if there is one.
+    fn cancellation(&self, uid: &str) -> Option<SentEvent> {
+        let mut event = self.events.get(uid)?.clone();
+        event.sequence += 1;
+        Some(event)
+    }
+
+    fn cancelled(&mut self, uid: &str, cancellation: &SentEvent) {
+        self.events.remove(uid);
+        self.cancelled.insert(
+            uid.to_owned(),
+            CancelledEvent {
+                sequence: cancellation.sequence,
+                end: cancellation.end,
+            },
+        );
+    }
+
+    /// Forgets cancelled invitations of events that ended before `current_date`, as those
+    /// aren't created again.
+    fn forget_cancelled_before(&mut self, current_date: NaiveDate) {
+        self.cancelled
+            .retain(|_, cancelled| cancelled.end.date() >= current_date);
+    }
+
+    /// Takes the response of `attendee_email` from a `REPLY`.
+    fn apply_reply(&mut self, calendar: &Component, attendee_email: &str) {
+        let is_reply = calendar
+            .property("METHOD")
+            .is_some_and(|method| method.value.eq_ignore_ascii_case("REPLY"));
+        if !is_reply {
+            return;
+        }
+
+        for vevent in calendar.components("VEVENT") {
+            let Some(event) = vevent
+                .property("UID")
+                .and_then(|uid| self.events.get_mut(&uid.value))
+            else {
+                continue;
+            };
+            let Some(attendee) = vevent.attendee(attendee_email) else {
+                continue;
+            };
+
+            let sequence = vevent
+                .property("SEQUENCE")
+                .and_then(|sequence| sequence.value.parse().ok())
+                .unwrap_or(0);
+            // a reply to an outdated invitation doesn't count for the new time
+            if sequence >= event.sequence {
+                event.attendance = ical::attendance_from_partstat(attendee.param("PARTSTAT"));
+            }
+        }
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(ImipState::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// [`CalendarBackend`] that sends iTIP invitations by mail (iMIP, RFC 6047), so any
+/// mail client with calendar support can show and answer them. Responses are read
+/// from the `REPLY` messages in the organizer's mailbox.
+pub struct ImipBackend {
+    config: ImipConfig,
+    attendee_email: String,
+    mailer: SmtpTransport,
+    state: ImipState,
+}
+
+pub fn smtp_error(err: impl std::fmt::Display) -> AutospielerError {
+    AutospielerError::Calendar(format!("SMTP: {err}"))
+}
+
+impl ImipBackend {
+    pub fn new(config: ImipConfig, attendee_email: &str) -> Result<Self, AutospielerError> {
+        let state = ImipState::load(&config.state_path)?;
+        Ok(ImipBackend {
+            mailer: config.smtp.transport()?,
+            config,
+            attendee_email: attendee_email.to_owned(),
+            state,
+        })
+    }
+
+    fn uid(&self, spielerplus_id: &str) -> String {
+        let name = format!("{}/{spielerplus_id}", self.attendee_email);
+        format!(
+            "{}@autospieler",
+            uuid::Uuid::new_v5(&UID_NAMESPACE, name.as_bytes())
+        )
+    }
+
+    fn vevent(&self, uid: &str, event: &SentEvent) -> Component {
+        let mut vevent = Component::event(
+            uid,
+            &NewCalendarEvent {
+                subject: event.subject.clone(),
+                body: event.body.clone(),
+                start: event.start,
+                end: event.end,
+                location: event.location.clone(),
+                spielerplus_id: event.spielerplus_id.clone(),
+                first_attempt: None,
+            },
+        );
+        vevent.set(Property::new("SEQUENCE", &event.sequence.to_string()));
+        vevent.push(Property::new(
+            "ORGANIZER",
+            &format!("mailto:{}", self.config.organizer_email),
+        ));
+        vevent.push(
+            Property::new("ATTENDEE", &format!("mailto:{}", self.attendee_email))
+                .with_param("CUTYPE", "INDIVIDUAL")
+                .with_param("ROLE", "REQ-PARTICIPANT")
+                .with_param("PARTSTAT", "NEEDS-ACTION")
+                .with_param("RSVP", "TRUE"),
+        );
+        vevent
+    }
+
+    fn send(&self, method: &str, uid: &str, event: &SentEvent) -> Result<(), AutospielerError> {
+        let mut vevent = self.vevent(uid, event);
+        let (subject, text) = if method == "CANCEL" {
+            vevent.set(Property::new("STATUS", "CANCELLED"));
+            (
+                format!("Abgesagt: {}", event.subject),
+                format!("{} wurde abgesagt.", event.subject),
+            )
+        } else {
+            (event.subject.clone(), event.body.clone())
+        };
+
+        let mut calendar = Component::calendar(Some(method));
+        calendar.components.push(vevent);
+
+        let parse_mailbox = |address: &str| {
+            address.parse::<Mailbox>().map_err(|e| {
+                AutospielerError::config(format!("invalid mail address {address}: {e}"))
+            })
+        };
+        let calendar_type =
+            ContentType::parse(&format!("text/calendar; charset=utf-8; method={method}"))
+                .map_err(smtp_error)?;
+
+        let message = Message::builder()
+            .from(parse_mailbox(&self.config.organizer_email)?)
+            .to(parse_mailbox(&self.attendee_email)?)
+            .subject(subject)
+            .multipart(
+                MultiPart::alternative()
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(ContentType::TEXT_PLAIN)
+                            .body(text),
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(calendar_type)
+                            .body(calendar.to_ical()),
+                    ),
+            )
+            .map_err(smtp_error)?;
+
+        self.mailer.send(&message).map_err(smtp_error)?;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), AutospielerError> {
+        Ok(self.state.save(&self.config.state_path)?)
+    }
+
+    /// Reads the replies that arrived since the last poll and updates the responses.
+    fn poll_replies(&mut self) -> Result<(), AutospielerError> {
+        let Some(imap) = &self.config.imap else {
+            return Ok(());
+        };
+
+        let mut session = ImapSession::connect(&imap.host, imap.port, imap.tls)?;
+        session.login(&imap.username, &imap.password)?;
+        let uid_validity = session.select(&imap.mailbox)?;
+        if self.state.imap_uid_validity != Some(uid_validity) {
+            // the UIDs were reassigned, so every message has to be read again
+            self.state.imap_uid_validity = Some(uid_validity);
+            self.state.imap_last_uid = 0;
+        }
+        let messages = session.fetch_after(self.state.imap_last_uid)?;
+        session.logout()?;
+
+        for (uid, message) in messages {
+            for reply in calendar_parts(&message) {
+                self.state.apply_reply(&reply, &self.attendee_email);
+            }
+            self.state.imap_last_uid = self.state.imap_last_uid.max(uid);
+        }
+        self.save()
+    }
+}
+
+/// Every `text/calendar` part of a raw mail.
+fn calendar_parts(message: &[u8]) -> Vec<Component> {
+    let Some(message) = MessageParser::default().parse(message) else {
+        return Vec::new();
+    };
+
+    message
+        .parts
+        .iter()
+        .filter(|part| {
+            part.content_type().is_some_and(|content_type| {
+                content_type.ctype().eq_ignore_ascii_case("text")
+                    && content_type
+                        .subtype()
+                        .is_some_and(|subtype| subtype.eq_ignore_ascii_case("calendar"))
+            })
+        })
+        .filter_map(|part| Component::parse(part.text_contents()?))
+        .collect()
+}
+
+impl CalendarBackend for ImipBackend {
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        self.poll_replies()?;
+        self.state.forget_cancelled_before(current_date);
+
+        Ok(self
+            .state
+            .events
+            .iter()
+            .filter(|(_, event)| event.start.date() >= current_date)
+            .map(|(uid, event)| (event.spielerplus_id.clone(), to_calendar_event(uid, event)))
+            .collect())
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        Ok(self
+            .state
+            .events
+            .get(event_id)
+            .map(|event| to_calendar_event(event_id, event)))
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        let uid = self.uid(&event.spielerplus_id);
+        let sent_event = self.state.invitation(&uid, event);
+
+        self.send("REQUEST", &uid, &sent_event)?;
+        self.state.invited(&uid, sent_event);
+        self.save()
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        let mut event = self
+            .state
+            .events
+            .get(event_id)
+            .cloned()
+            .ok_or_else(|| AutospielerError::Calendar(format!("unknown event {event_id}")))?;
+        event.start = start;
+        event.end = end;
+        event.sequence += 1;
+
+        self.send("REQUEST", event_id, &event)?;
+        self.state.events.insert(event_id.to_owned(), event);
+        self.save()
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        let Some(cancellation) = self.state.cancellation(event_id) else {
+            return Ok(());
+        };
+
+        self.send("CANCEL", event_id, &cancellation)?;
+        self.state.cancelled(event_id, &cancellation);
+        self.save()
+    }
+}
+
+fn to_calendar_event(uid: &str, event: &SentEvent) -> CalendarEvent {
+    CalendarEvent {
+        id: uid.to_owned(),
+        spielerplus_id: event.spielerplus_id.clone(),
+        subject: event.subject.clone(),
+        start: event.start,
+        end: event.end,
+        attendance: event.attendance,
+        response_time: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: &str = "0f3c2d1e-5a6b-5c7d-8e9f-a0b1c2d3e4f5@autospieler";
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn training() -> NewCalendarEvent {
+        NewCalendarEvent {
+            subject: "Training".to_owned(),
+            body: String::new(),
+            start: at("2024-03-12 19:30"),
+            end: at("2024-03-12 21:00"),
+            location: "Sporthalle".to_owned(),
+            spielerplus_id: "12345".to_owned(),
+            first_attempt: None,
+        }
+    }
+
+    #[test]
+    fn continues_the_sequence_of_a_cancelled_invitation() {
+        let mut state = ImipState::default();
+
+        let invitation = state.invitation(UID, &training());
+        assert_eq!(invitation.sequence, 0);
+        state.invited(UID, invitation);
+
+        let cancellation = state.cancellation(UID).unwrap();
+        assert_eq!(cancellation.sequence, 1);
+        state.cancelled(UID, &cancellation);
+        assert!(state.events.is_empty());
+        assert!(state.cancellation(UID).is_none());
+
+        // Spielerplus brought the event back
+        let invitation = state.invitation(UID, &training());
+        assert_eq!(invitation.sequence, 2);
+        state.invited(UID, invitation);
+        assert!(state.cancelled.is_empty());
+        assert_eq!(state.cancellation(UID).unwrap().sequence, 3);
+    }
+
+    #[test]
+    fn sends_an_unchanged_invitation_again() {
+        let mut state = ImipState::default();
+        let mut invitation = state.invitation(UID, &training());
+        invitation.sequence = 4;
+        state.invited(UID, invitation);
+
+        assert_eq!(state.invitation(UID, &training()).sequence, 4);
+    }
+
+    #[test]
+    fn forgets_cancelled_invitations_of_past_events() {
+        let mut state = ImipState::default();
+        state.invited(UID, state.invitation(UID, &training()));
+        state.cancelled(UID, &state.cancellation(UID).unwrap());
+
+        state.forget_cancelled_before(NaiveDate::from_ymd_opt(2024, 3, 12).unwrap());
+        assert!(state.cancelled.contains_key(UID));
+        state.forget_cancelled_before(NaiveDate::from_ymd_opt(2024, 3, 13).unwrap());
+        assert!(state.cancelled.is_empty());
+    }
+
+    /// A reply mail of `player@example.com` with `partstat` to the invitation `sequence`.
+    fn reply_mail(partstat: &str, sequence: u32) -> String {
+        format!(
+            "From: player@example.com\r
+To: organizer@example.com\r
+Subject: Accepted: Training\r
+MIME-Version: 1.0\r
+Content-Type: multipart/alternative; boundary=\"reply\"\r
+\r
+--reply\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+Player has answered.\r
+--reply\r
+Content-Type: text/calendar; charset=utf-8; method=REPLY\r
+\r
+BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Example//Mail//EN\r
+METHOD:REPLY\r
+BEGIN:VEVENT\r
+UID:{UID}\r
+SEQUENCE:{sequence}\r
+DTSTAMP:20240311T120000Z\r
+ATTENDEE;PARTSTAT={partstat}:mailto:Player@Example.com\r
+ORGANIZER:mailto:organizer@example.com\r
+END:VEVENT\r
+END:VCALENDAR\r
+--reply--\r
+"
+        )
+    }
+
+    fn answer_after(state: &mut ImipState, mail: &str) -> Option<Attendance> {
+        for reply in calendar_parts(mail.as_bytes()) {
+            state.apply_reply(&reply, "player@example.com");
+        }
+        state.events[UID].attendance
+    }
+
+    #[test]
+    fn reads_replies_from_mails() {
+        let mut state = ImipState::default();
+        let mut invitation = state.invitation(UID, &training());
+        invitation.sequence = 1;
+        state.invited(UID, invitation);
+
+        assert_eq!(
+            answer_after(&mut state, &reply_mail("ACCEPTED", 1)),
+            Some(Attendance::Accepted)
+        );
+        assert_eq!(
+            answer_after(&mut state, &reply_mail("TENTATIVE", 2)),
+            Some(Attendance::Unsure)
+        );
+        // a reply to the invitation before the time changed
+        assert_eq!(
+            answer_after(&mut state, &reply_mail("DECLINED", 0)),
+            Some(Attendance::Unsure)
+        );
+    }
+
+    #[test]
+    fn ignores_replies_to_unknown_invitations_and_other_methods() {
+        let mut state = ImipState::default();
+        state.invited(UID, state.invitation(UID, &training()));
+
+        let request = reply_mail("ACCEPTED", 0).replace("METHOD:REPLY", "METHOD:REQUEST");
+        assert_eq!(answer_after(&mut state, &request), None);
+
+        let unknown = reply_mail("ACCEPTED", 0).replace(UID, "other@autospieler");
+        assert_eq!(answer_after(&mut state, &unknown), None);
+    }
+}
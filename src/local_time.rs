@@ -0,0 +1,111 @@
+use crate::error::AutospielerError;
+use chrono::{NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Time zone Spielerplus shows its times in, all local times are in it.
+pub const LOCAL_TIME_ZONE: Tz = chrono_tz::Europe::Zurich;
+
+/// Converts a UTC time to [`LOCAL_TIME_ZONE`].
+pub fn utc_to_local(utc: NaiveDateTime) -> NaiveDateTime {
+    LOCAL_TIME_ZONE.from_utc_datetime(&utc).naive_local()
+}
+
+/// Converts a time in `zone` to [`LOCAL_TIME_ZONE`], `None` if the time doesn't exist
+/// there because the clocks were put forward.
+pub fn zone_to_local(date_time: NaiveDateTime, zone: Tz) -> Option<NaiveDateTime> {
+    let in_zone = zone.from_local_datetime(&date_time).earliest()?;
+    Some(in_zone.with_timezone(&LOCAL_TIME_ZONE).naive_local())
+}
+
+/// The current time in [`LOCAL_TIME_ZONE`].
+pub fn local_now() -> NaiveDateTime {
+    Utc::now().with_timezone(&LOCAL_TIME_ZONE).naive_local()
+}
+
+/// Local time window like `18:00-22:00`, may wrap around midnight like `22:00-06:00`.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn parse(input: &str) -> Result<Self, AutospielerError> {
+        let invalid = || {
+            AutospielerError::config(format!(
+                "time window '{input}' is not in the format HH:MM-HH:MM"
+            ))
+        };
+        let (start, end) = input.split_once("-").ok_or_else(invalid)?;
+
+        Ok(TimeWindow {
+            start: NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?,
+            end: NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?,
+        })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // the window wraps around midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = AutospielerError;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        TimeWindow::parse(&input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn converts_utc_around_the_daylight_saving_switches() {
+        assert_eq!(utc_to_local(at("2024-03-31 00:59")), at("2024-03-31 01:59"));
+        assert_eq!(utc_to_local(at("2024-03-31 01:00")), at("2024-03-31 03:00"));
+        assert_eq!(utc_to_local(at("2024-10-27 00:59")), at("2024-10-27 02:59"));
+        assert_eq!(utc_to_local(at("2024-10-27 01:00")), at("2024-10-27 02:00"));
+    }
+
+    #[test]
+    fn converts_utc_before_switzerland_had_summer_time() {
+        assert_eq!(utc_to_local(at("1980-07-01 12:00")), at("1980-07-01 13:00"));
+        assert_eq!(utc_to_local(at("1981-07-01 12:00")), at("1981-07-01 14:00"));
+    }
+
+    #[test]
+    fn converts_other_zones() {
+        assert_eq!(
+            zone_to_local(at("2024-07-01 08:00"), chrono_tz::America::New_York),
+            Some(at("2024-07-01 14:00"))
+        );
+        assert_eq!(
+            zone_to_local(at("2024-03-10 02:30"), chrono_tz::America::New_York),
+            None
+        );
+    }
+
+    #[test]
+    fn contains_times_in_windows_wrapping_around_midnight() {
+        let window = TimeWindow::parse("22:00-06:00").unwrap();
+        let time = |input| NaiveTime::parse_from_str(input, "%H:%M").unwrap();
+
+        assert!(window.contains(time("23:30")));
+        assert!(window.contains(time("05:59")));
+        assert!(!window.contains(time("06:00")));
+        assert!(!window.contains(time("12:00")));
+        assert!(TimeWindow::parse("22:00").is_err());
+    }
+}
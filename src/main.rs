@@ -1,14 +1,135 @@
-use chrono::{Datelike, NaiveTime};
-use office::{cancel_event, update_event_time};
+use answers::AnswerSource;
+use calendar::{Attendance, CalendarBackend, CalendarChange};
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use error::AutospielerError;
 use reqwest::blocking::Client;
-use std::{collections::HashMap, env};
+use retry::SendWithRetry;
+use std::{collections::HashMap, env, path::PathBuf, process::ExitCode};
+pub mod absences;
+pub mod answers;
+pub mod attendance;
+pub mod caldav;
+pub mod calendar;
+pub mod conflicts;
+pub mod daemon;
+pub mod deadlines;
+pub mod error;
+pub mod filters;
+pub mod google;
+pub mod holidays;
+pub mod ical;
+pub mod ics;
+pub mod imap;
+pub mod imip;
+pub mod local_time;
+pub mod notifications;
 pub mod office;
+pub mod retry;
+pub mod rules;
+pub mod spielerplus;
+#[cfg(test)]
+mod stub_server;
+
+/// Reads the attendance from the selected button of a `.participation-widget-buttons` element.
+fn selected_attendance(
+    widget_buttons: &scraper::ElementRef,
+    selected_selector: &scraper::Selector,
+) -> Result<Option<Attendance>, AutospielerError> {
+    let Some(selected_button) = widget_buttons.select(selected_selector).next() else {
+        return Ok(None);
+    };
+
+    let attendance = match selected_button
+        .value()
+        .attr("title")
+        .ok_or_else(|| AutospielerError::markup("missing selected_button attr 'title'"))?
+    {
+        "Zugesagt" | "Confirmed" => Attendance::Accepted,
+        "Unsicher" | "Unsure" => Attendance::Unsure,
+        "Absagen / Abwesend" | "Declined / Absent" => Attendance::Declined,
+        other => {
+            return Err(AutospielerError::markup(format!(
+                "Unknown selected Zusage button title '{other}'"
+            )))
+        }
+    };
+
+    Ok(Some(attendance))
+}
+
+/// Whether the participation can't be changed anymore, e.g. because the coach locked the
+/// event. Spielerplus then disables the buttons of the widget.
+fn is_participation_locked(widget_buttons: &scraper::ElementRef) -> bool {
+    let is_disabled = |element: &scraper::ElementRef| {
+        let element = element.value();
+        element.attr("disabled").is_some()
+            || element
+                .classes()
+                .any(|class| matches!(class, "disabled" | "locked" | "readonly" | "read-only"))
+    };
+
+    let mut buttons = widget_buttons
+        .children()
+        .filter_map(scraper::ElementRef::wrap)
+        .peekable();
+    is_disabled(widget_buttons)
+        || (buttons.peek().is_some() && buttons.all(|button| is_disabled(&button)))
+}
+
+/// Body of the calendar event of a Spielerplus event.
+fn event_body(holiday: Option<&str>, answer_locked: bool) -> String {
+    let mut body =
+        "New training found in Spielerplus. Please accept/decline this event.".to_owned();
+    if let Some(holiday) = holiday {
+        body.push_str(&format!(" It's on a public holiday: {holiday}."));
+    }
+    if answer_locked {
+        body.push_str(
+            "\n\nYour answer could not be applied, the participation in Spielerplus is locked.",
+        );
+    }
+    body
+}
+
+/// Moves the calendar event to the time of its Spielerplus event. The end is only
+/// compared if Spielerplus shows one (`override_end`), otherwise it's made up.
+fn time_change(
+    event: &calendar::CalendarEvent,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    override_end: bool,
+) -> Option<CalendarChange> {
+    (event.start != start || (event.end != end && override_end)).then(|| {
+        CalendarChange::UpdateTime {
+            event_id: event.id.clone(),
+            start,
+            end,
+        }
+    })
+}
 
-#[derive(PartialEq, Eq)]
-enum Attendance {
-    ACCEPTED,
-    UNSURE,
-    DECLINED,
+/// Cancels the calendar events whose Spielerplus event wasn't handled in this sync,
+/// because it's gone or filtered out.
+fn cancellations(
+    calendar_events: calendar::CalendarEventMap,
+    handled_training_ids: &[String],
+    filtered_training_ids: &[String],
+) -> Vec<CalendarChange> {
+    let mut changes = Vec::new();
+    for event in calendar_events {
+        if handled_training_ids.contains(&event.0) {
+            continue;
+        }
+        if filtered_training_ids.contains(&event.0) {
+            println!("{} is filtered out, deleting...", event.0);
+        } else {
+            println!("didn't handle {}, deleting...", event.0);
+        }
+        changes.push(CalendarChange::Cancel {
+            event_id: event.1.id,
+        });
+    }
+    changes
 }
 
 fn parse_sp_timestring(input: &str) -> Option<String> {
@@ -50,16 +171,16 @@ fn set_attendence(
     event_type: &str,
     reason: &str,
     participation_type: Attendance,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), AutospielerError> {
     let res = client
         .post("https://www.spielerplus.de/events/ajax-participation-form")
         .form(&[
             (
                 "Participation[participation]",
                 match participation_type {
-                    Attendance::ACCEPTED => "1",
-                    Attendance::UNSURE => "2",
-                    Attendance::DECLINED => "0",
+                    Attendance::Accepted => "1",
+                    Attendance::Unsure => "2",
+                    Attendance::Declined => "0",
                 },
             ),
             ("Participation[reason]", reason),
@@ -67,68 +188,516 @@ fn set_attendence(
             ("Participation[typeid]", event_id),
             ("Participation[user_id]", user_id),
         ])
-        .send()?;
+        .send_with_retry()?;
 
     if res.status() != reqwest::StatusCode::from_u16(200).unwrap() {
-        return Err("/events/ajax-participation-form response status is not '200 OK'".into());
+        return Err(AutospielerError::Spielerplus(format!(
+            "/events/ajax-participation-form response status is '{}' instead of '200 OK'",
+            res.status()
+        )));
+    }
+
+    verify_participation(&res.text()?, event_id, participation_type)?;
+    println!("Answered {event_id} with {participation_type:?}");
+
+    Ok(())
+}
+
+/// Fails unless the response of `/events/ajax-participation-form` shows that Spielerplus
+/// applied `answer`, so an answer is only recorded once it's known to be there.
+fn verify_participation(
+    body: &str,
+    event_id: &str,
+    answer: Attendance,
+) -> Result<(), AutospielerError> {
+    let rejected = |message: String| AutospielerError::ParticipationRejected {
+        event_id: event_id.to_owned(),
+        message,
+    };
+    match participation_response(body, answer)? {
+        ParticipationResponse::Applied => Ok(()),
+        ParticipationResponse::Unverified => Err(AutospielerError::ParticipationUnverified {
+            event_id: event_id.to_owned(),
+        }),
+        ParticipationResponse::LoggedOut => Err(AutospielerError::Authentication(
+            "the Spielerplus session expired".to_owned(),
+        )),
+        ParticipationResponse::Rejected(errors) => Err(rejected(errors.join(", "))),
+        ParticipationResponse::Selected(selected) => Err(rejected(format!(
+            "the participation is {selected:?} instead of {answer:?}"
+        ))),
+    }
+}
+
+/// What the response of `/events/ajax-participation-form` says about an answer.
+#[derive(Debug, PartialEq)]
+enum ParticipationResponse {
+    Applied,
+    /// No errors, but the response doesn't contain the participation either.
+    Unverified,
+    /// The login page instead of the form, the session expired.
+    LoggedOut,
+    /// Error messages, e.g. that a reason is required.
+    Rejected(Vec<String>),
+    /// The participation Spielerplus shows, if it's not the answer.
+    Selected(Option<Attendance>),
+}
+
+/// Reads the response of `/events/ajax-participation-form`, a JSON object or the HTML of the
+/// participation form with its validation errors.
+fn participation_response(
+    body: &str,
+    answer: Attendance,
+) -> Result<ParticipationResponse, AutospielerError> {
+    let body = body.trim();
+    let html = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => {
+            let mut errors = Vec::new();
+            collect_json_errors(&json["errors"], &mut errors);
+            if json["success"] == false || json["status"] == "error" {
+                collect_json_errors(&json["message"], &mut errors);
+                if errors.is_empty() {
+                    errors.push("no reason given".to_owned());
+                }
+            }
+            if !errors.is_empty() {
+                return Ok(ParticipationResponse::Rejected(errors));
+            }
+            match json["html"].as_str() {
+                Some(html) => html.to_owned(),
+                None => return Ok(ParticipationResponse::Unverified),
+            }
+        }
+        Err(_) => body.to_owned(),
+    };
+
+    let fragment = scraper::Html::parse_fragment(&html);
+    if spielerplus::is_login_page(&fragment) {
+        return Ok(ParticipationResponse::LoggedOut);
+    }
+    let error_selector = scraper::Selector::parse(
+        ".has-error .help-block, .help-block-error, .error-summary li, .alert-danger, .invalid-feedback",
+    )
+    .unwrap();
+    let mut errors: Vec<String> = fragment
+        .select(&error_selector)
+        .map(|element| {
+            element
+                .text()
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|text| !text.is_empty())
+        .collect();
+    if !errors.is_empty() {
+        errors.dedup();
+        return Ok(ParticipationResponse::Rejected(errors));
+    }
+
+    let widget_buttons_selector =
+        scraper::Selector::parse(".participation-widget-buttons").unwrap();
+    let selected_selector = scraper::Selector::parse(".selected").unwrap();
+    let Some(widget_buttons) = fragment.select(&widget_buttons_selector).next() else {
+        return Ok(ParticipationResponse::Unverified);
+    };
+    match selected_attendance(&widget_buttons, &selected_selector)? {
+        Some(selected) if selected == answer => Ok(ParticipationResponse::Applied),
+        selected => Ok(ParticipationResponse::Selected(selected)),
+    }
+}
+
+/// Collects the messages of a JSON error, a string, a list or an object of them by field.
+fn collect_json_errors(value: &serde_json::Value, errors: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(message) if !message.trim().is_empty() => {
+            errors.push(message.trim().to_owned())
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_json_errors(value, errors);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for value in fields.values() {
+                collect_json_errors(value, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_env(name: &str) -> Result<String, AutospielerError> {
+    env::var(name).map_err(|e| {
+        AutospielerError::config(format!("Could not read environment variable {name}: {e}"))
+    })
+}
+
+fn read_optional_env(name: &str) -> Result<Option<String>, AutospielerError> {
+    match env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(AutospielerError::config(format!(
+            "Could not read environment variable {name}: {e}"
+        ))),
+    }
+}
+
+fn read_parsed_env<T>(name: &str, default: T) -> Result<T, AutospielerError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match read_optional_env(name)? {
+        Some(value) => value.trim().parse().map_err(|e| {
+            AutospielerError::config(format!("Could not parse environment variable {name}: {e}"))
+        }),
+        None => Ok(default),
+    }
+}
+
+struct Config {
+    team_selection: spielerplus::TeamSelection,
+    event_filters: filters::EventFilters,
+    rules: rules::DauerzusageRules,
+    conflict_answers: conflicts::ConflictAnswers,
+    absences: absences::AbsenceConfig,
+    holidays: Option<holidays::HolidayCalendar>,
+    deadlines: deadlines::DeadlineConfig,
+    user_mail: String,
+    user_password: String,
+    state_dir: PathBuf,
+}
+
+fn state_dir_from_env() -> Result<PathBuf, AutospielerError> {
+    Ok(read_optional_env("AUTOSPIELER_STATE_DIR")?
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(".")))
+}
+
+impl Config {
+    fn outlook_delta_path(&self) -> PathBuf {
+        self.state_dir.join("outlook-delta.json")
+    }
+
+    fn attendance_state_path(&self) -> PathBuf {
+        self.state_dir.join("attendance-state.json")
+    }
+}
+
+/// What the last sync learned about a Spielerplus event, enough to answer it
+/// later without scraping the events page again.
+struct SpielerplusEvent {
+    user_id: String,
+    event_type: String,
+    attendance: Option<Attendance>,
+    /// Whether the participation can't be changed anymore.
+    locked: bool,
+    holiday: Option<&'static str>,
+}
+
+/// State that outlives a single sync: the Spielerplus cookie session, the
+/// calendar backend and what was scraped from Spielerplus.
+struct Session {
+    config: Config,
+    client: Client,
+    calendar: Box<dyn CalendarBackend>,
+    attendance: attendance::AttendanceStore,
+    teams: Vec<spielerplus::Team>,
+    spielerplus_events: HashMap<String, SpielerplusEvent>,
+}
+
+/// Builds the calendar backend selected by `CALENDAR_BACKEND`.
+fn calendar_from_env(config: &Config) -> Result<Box<dyn CalendarBackend>, AutospielerError> {
+    match read_optional_env("CALENDAR_BACKEND")?.as_deref() {
+        None | Some("outlook") => {
+            // Graph doesn't need the Spielerplus cookies, so it gets its own connection pool.
+            let graph_client = Client::new();
+            let token_provider = office::MicrosoftTokenCache::new(
+                graph_client.clone(),
+                &read_env("ENTRA_CLIENT_ID")?,
+                &read_env("ENTRA_CLIENT_SECRET")?,
+                &read_env("ENTRA_TENANT_ID")?,
+            );
+            let mut graph = office::GraphCalendarClient::new(
+                graph_client,
+                Box::new(token_provider),
+                &read_env("OUTLOOK_USER_PRINCIPAL_NAME")?,
+                &read_env("OUTLOOK_CALENDAR_ID")?,
+            );
+            if let Some(base_url) = read_optional_env("GRAPH_BASE_URL")? {
+                graph = graph.with_base_url(&base_url);
+            }
+
+            Ok(Box::new(office::OutlookBackend::new(
+                graph,
+                &config.user_mail,
+                config.outlook_delta_path(),
+            )?))
+        }
+        Some("caldav") => Ok(Box::new(caldav::CalDavBackend::new(
+            Client::new(),
+            caldav::CalDavConfig::from_env()?,
+            &config.user_mail,
+        ))),
+        Some("google") => Ok(Box::new(google::GoogleCalendarBackend::new(
+            Client::new(),
+            google::GoogleConfig::from_env()?,
+            &config.user_mail,
+        ))),
+        Some("ics") => Ok(Box::new(ics::IcsFeedBackend::new(
+            ics::IcsFeedConfig::from_env(&config.state_dir)?.path,
+        )?)),
+        Some("imip") => Ok(Box::new(imip::ImipBackend::new(
+            imip::ImipConfig::from_env(&config.state_dir)?,
+            &config.user_mail,
+        )?)),
+        Some("memory") => Ok(Box::new(calendar::InMemoryBackend::default())),
+        Some(other) => Err(AutospielerError::config(format!(
+            "unknown CALENDAR_BACKEND '{other}', expected 'outlook', 'caldav', 'google', 'ics', 'imip' or 'memory'"
+        ))),
+    }
+}
+
+impl Session {
+    fn from_env() -> Result<Self, AutospielerError> {
+        let state_dir = state_dir_from_env()?;
+        let config = Config {
+            team_selection: spielerplus::TeamSelection::from_env()?,
+            event_filters: filters::EventFilters::from_env()?,
+            rules: rules::DauerzusageRules::from_env()?,
+            conflict_answers: conflicts::ConflictAnswers::from_env()?,
+            absences: absences::AbsenceConfig::from_env(&state_dir)?,
+            holidays: holidays::HolidayCalendar::from_env()?,
+            deadlines: deadlines::DeadlineConfig::from_env()?,
+            user_mail: read_env("DAUERZUSAGE_EMAIL")?,
+            user_password: read_env("DAUERZUSAGE_PASSWORT")?,
+            state_dir,
+        };
+
+        let calendar = calendar_from_env(&config)?;
+        let attendance = attendance::AttendanceStore::load(
+            config.attendance_state_path(),
+            attendance::ConflictPolicy::from_env()?,
+        )?;
+
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_store(true)
+            .build()?;
+
+        Ok(Session {
+            config,
+            client,
+            calendar,
+            attendance,
+            teams: Vec::new(),
+            spielerplus_events: HashMap::new(),
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), AutospielerError> {
+    let command = env::args().nth(1);
+    retry::set_policy(retry::RetryPolicy::from_env()?);
+
+    match command.as_deref() {
+        None | Some("sync") => sync(&mut Session::from_env()?),
+        Some("absence") => absences::command(
+            &absences::AbsenceConfig::from_env(&state_dir_from_env()?)?,
+            &env::args().skip(2).collect::<Vec<_>>(),
+        ),
+        Some("daemon") => {
+            let daemon_config = daemon::DaemonConfig::from_env()?;
+            let notification_config = notifications::NotificationConfig::from_env()?;
+            let mut session = Session::from_env()?;
+
+            if read_optional_env("CALENDAR_BACKEND")?.as_deref() == Some("ics") {
+                let ics_config = ics::IcsFeedConfig::from_env(&session.config.state_dir)?;
+                if let Some(listen_address) = &ics_config.listen_address {
+                    ics::start_feed_server(listen_address, ics_config.path)?;
+                }
+            }
+
+            let changed_events = notification_config
+                .as_ref()
+                .map(notifications::start_listener)
+                .transpose()?;
+
+            daemon::run(&daemon_config, changed_events, |job| match job {
+                daemon::DaemonJob::Sync => {
+                    if let Some(notification_config) = &notification_config {
+                        if let Err(err) = renew_subscription(&mut session, notification_config) {
+                            eprintln!(
+                                "could not renew the change notification subscription: {err}"
+                            );
+                        }
+                    }
+                    sync(&mut session)
+                }
+                daemon::DaemonJob::OutlookEventChanged(outlook_event_id) => {
+                    apply_outlook_event_change(&mut session, &outlook_event_id)
+                }
+            })
+        }
+        Some(other) => Err(AutospielerError::config(format!(
+            "unknown command '{other}', expected 'sync', 'daemon' or 'absence'"
+        ))),
+    }
+}
+
+/// Makes sure the calendar keeps posting change notifications to the listener.
+fn renew_subscription(
+    session: &mut Session,
+    notification_config: &notifications::NotificationConfig,
+) -> Result<(), AutospielerError> {
+    let Some(notification_url) = &notification_config.notification_url else {
+        return Ok(());
+    };
+
+    session
+        .calendar
+        .ensure_change_notifications(notification_url, &notification_config.client_state)
+}
+
+/// Applies the attendee response of a single Outlook event to Spielerplus, using what
+/// the last sync scraped instead of going through all events again.
+fn apply_outlook_event_change(
+    session: &mut Session,
+    outlook_event_id: &str,
+) -> Result<(), AutospielerError> {
+    if session.calendar.read_only() {
+        return Ok(());
+    }
+    let Some(event) = session.calendar.get_event(outlook_event_id)? else {
+        return Ok(());
+    };
+
+    let spielerplus_id = event.spielerplus_id.as_str();
+    let Some(user_id) = session
+        .spielerplus_events
+        .get(spielerplus_id)
+        .map(|spielerplus_event| spielerplus_event.user_id.clone())
+    else {
+        log::info!("Spielerplus event {spielerplus_id} is unknown, leaving it to the next sync");
+        return Ok(());
+    };
+
+    // Nothing is recorded before Spielerplus is known to be logged in, so a full sync,
+    // which logs in again, sees the response as changed.
+    match spielerplus::switch_user(&session.client, &user_id) {
+        Err(AutospielerError::Authentication(message)) => {
+            println!("{message}, syncing all events for the change of {spielerplus_id}");
+            return sync(session);
+        }
+        result => result?,
+    }
+    let Some(spielerplus_event) = session.spielerplus_events.get_mut(spielerplus_id) else {
+        return Ok(());
+    };
+
+    let resolved = session.attendance.resolve(
+        spielerplus_id,
+        event.attendance,
+        event.response_time,
+        spielerplus_event.attendance,
+    );
+    let Some(new_attendance) = resolved else {
+        return Ok(());
+    };
+    if spielerplus_event.attendance == Some(new_attendance) {
+        return Ok(());
     }
 
-    println!("{}", res.status());
+    if spielerplus_event.locked {
+        println!(
+            "Not answering {spielerplus_id} with {new_attendance:?}, its participation is locked"
+        );
+        if !session.attendance.locked_noted(spielerplus_id) {
+            session
+                .calendar
+                .update_event_body(&event.id, &event_body(spielerplus_event.holiday, true))?;
+            session.attendance.set_locked_noted(spielerplus_id);
+            session.attendance.save()?;
+        }
+        return Ok(());
+    }
+
+    println!("Outlook response changed for {spielerplus_id}, updating Spielerplus");
+
+    set_attendence(
+        &session.client,
+        &spielerplus_event.user_id,
+        spielerplus_id,
+        &spielerplus_event.event_type,
+        "-",
+        new_attendance,
+    )?;
+    spielerplus_event.attendance = Some(new_attendance);
+    session
+        .attendance
+        .set_spielerplus(spielerplus_id, new_attendance);
+    session.attendance.save()?;
 
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let entra_client_id = env::var("ENTRA_CLIENT_ID")
-        .map_err(|e| format!("Could not read environment variable ENTRA_CLIENT_ID: {e}"))?;
-    let entra_client_secret = env::var("ENTRA_CLIENT_SECRET")
-        .map_err(|e| format!("Could not read environment variable ENTRA_CLIENT_SECRET: {e}"))?;
-    let entra_tenant_id = env::var("ENTRA_TENANT_ID")
-        .map_err(|e| format!("Could not read environment variable ENTRA_TENANT_ID: {e}"))?;
-    let outlook_user_principal_name = env::var("OUTLOOK_USER_PRINCIPAL_NAME").map_err(|e| {
-        format!("Could not read environment variable OUTLOOK_USER_PRINCIPAL_NAME: {e}")
-    })?;
-    let outlook_calendar_id = env::var("OUTLOOK_CALENDAR_ID")
-        .map_err(|e| format!("Could not read environment variable OUTLOOK_CALENDAR_ID: {e}"))?;
-
-    let microsoft_token =
-        office::get_microsoft_token(&entra_client_id, &entra_client_secret, &entra_tenant_id)?;
+fn sync(session: &mut Session) -> Result<(), AutospielerError> {
+    let Session {
+        config,
+        client,
+        calendar,
+        attendance,
+        teams,
+        spielerplus_events,
+        ..
+    } = session;
+    let Config {
+        team_selection,
+        event_filters,
+        rules,
+        conflict_answers,
+        absences: absence_config,
+        holidays,
+        deadlines,
+        user_mail,
+        user_password,
+        ..
+    } = &*config;
 
     let current_date = chrono::Utc::now();
+    let now = local_time::local_now();
     let current_year = current_date.year();
     let last_month = current_date.month() - 1;
-    let date_string = format!(
-        "{}-{}-{}",
-        current_year,
-        current_date.month(),
-        current_date.day()
-    );
 
-    let user_id_variable = env::var("DAUERZUSAGE_ID")
-        .map_err(|e| format!("Could not read environment variable DAUERZUSAGE_ID: {e}"))?;
-    let user_ids = user_id_variable.split(",").collect::<Vec<&str>>();
-    let user_mail = env::var("DAUERZUSAGE_EMAIL")
-        .map_err(|e| format!("Could not read environment variable DAUERZUSAGE_EMAIL: {e}"))?;
-    let user_password = env::var("DAUERZUSAGE_PASSWORT")
-        .map_err(|e| format!("Could not read environment variable DAUERZUSAGE_PASSWORT: {e}"))?;
-
-    let outlook_events = office::list_outlook_events(
-        &outlook_user_principal_name,
-        &outlook_calendar_id,
-        &date_string,
-        &microsoft_token,
-        &user_mail,
-    )?;
+    let calendar_events = calendar.list_events(current_date.date_naive())?;
+    let absences = absences::Absences::load(absence_config, calendar.as_mut())?;
+    let answerers = answers::Answerers {
+        absences: &absences,
+        conflict_answers,
+        rules,
+        deadlines,
+    };
+    // set once the calendar couldn't tell, so it isn't asked again for every event
+    let mut busy_times_failed = false;
+    let read_only = calendar.read_only();
 
     let url = "https://www.spielerplus.de/events";
 
     log::info!("Fetching {:?}...", url);
 
-    let client = reqwest::blocking::ClientBuilder::new()
-        .cookie_store(true)
-        .build()?;
-
-    let res = client.get(url).send()?;
+    let res = client.get(url).send_with_retry()?;
     log::info!("/events response: {:?} {}", res.version(), res.status());
 
     let text = res.text()?;
@@ -141,12 +710,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .select(&title_selector)
         .map(|x| x.inner_html());
 
-    let select_team_selector = scraper::Selector::parse(".select-team-item").unwrap();
-    let team_name_selector = scraper::Selector::parse(".select-team-item-meta h4").unwrap();
-    let team_link_selector = scraper::Selector::parse("a").unwrap();
-    let mut team_name_map = HashMap::<String, String>::new();
-
-    let mut title = titles.next().ok_or("missing title")?;
+    let mut title = titles
+        .next()
+        .ok_or_else(|| AutospielerError::markup("missing title"))?;
     if title == "Einloggen" {
         log::info!("Login required");
         let csrf_token_selector =
@@ -154,60 +720,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let csrf_token_element = document_events
             .select(&csrf_token_selector)
             .next()
-            .ok_or("missing csrf token")?;
+            .ok_or_else(|| AutospielerError::markup("missing csrf token"))?;
         let csrf_token = csrf_token_element
             .value()
             .attr("value")
-            .ok_or("missing value in csrf token")?;
+            .ok_or_else(|| AutospielerError::markup("missing value in csrf token"))?;
         let res = client
             .post("https://www.spielerplus.de/site/login")
             .form(&[
                 ("_csrf", csrf_token),
-                ("LoginForm[email]", &user_mail),
-                ("LoginForm[password]", &user_password),
+                ("LoginForm[email]", user_mail),
+                ("LoginForm[password]", user_password),
             ])
-            .send()?;
+            .send_with_retry_if_throttled()?;
 
         log::info!("/site/login response: {:?} {}", res.version(), res.status());
 
         let text = res.text()?;
         let document = scraper::Html::parse_document(&text);
-        let mut titles = document.select(&title_selector).map(|x| x.inner_html());
-        if let Some(title) = titles.next() {
-            if title == "Team auswählen" || title == "Select team" {
-                // alright!
-            } else {
-                return Err(format!(
-                    "title is not 'Team auswählen' or 'Select team', but '{}'",
-                    title
-                )
-                .into());
-            }
-        } else {
-            return Err("title missing".into());
-        }
-
-        for team in document.select(&select_team_selector).into_iter() {
-            let team_title = team
-                .select(&team_name_selector)
+        if !spielerplus::is_team_selection(&document) {
+            let title = document
+                .select(&title_selector)
                 .next()
-                .ok_or("no team name found in team")?
+                .ok_or_else(|| AutospielerError::markup("title missing"))?
                 .inner_html();
+            return Err(AutospielerError::Authentication(format!(
+                "Spielerplus title is not 'Team auswählen' or 'Select team', but '{}'",
+                title
+            )));
+        }
 
-            let team_id = team
-                .select(&team_link_selector)
-                .next()
-                .ok_or("no team link found in team")?
-                .value()
-                .attr("href")
-                .ok_or("no href attribute found in team link")?
-                .split_once("=")
-                .iter()
-                .last()
-                .ok_or("no id found in team link href attribute")?
-                .1;
-
-            team_name_map.insert(team_id.to_string(), team_title);
+        *teams = spielerplus::parse_teams(&document)?;
+    } else {
+        // the session is still logged in, but teams may have been joined or renamed
+        match spielerplus::list_teams(client) {
+            Ok(current_teams) => *teams = current_teams,
+            Err(err) => eprintln!("could not read the teams: {err}"),
         }
     }
 
@@ -224,28 +772,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let participation_widget_buttons_selector =
         scraper::Selector::parse(".participation-widget-buttons").unwrap();
     let selected_selector = scraper::Selector::parse(".selected").unwrap();
+    let deadline_selector = scraper::Selector::parse("[class*=\"deadline\"]").unwrap();
 
-    // let events = user_ids.iter().filter_map(
-    //     |user_id: &&str| -> Result<Vec<EnrichedHTMLEvent>, Box<dyn Error>> {
-    //         if user_id.starts_with("pat") {
-    //             return Ok(vec![EnrichedHTMLEvent { user_id: user_id }]);
-    //         }
-    //         Err("i'm a tree".into())
-    //     },
-    // );
+    let mut handled_training_ids = Vec::new();
+    let mut calendar_changes = Vec::new();
+    // changes noting a locked participation, by index, with the Spielerplus id
+    let mut locked_notes = Vec::new();
+    spielerplus_events.clear();
+
+    let user_ids = team_selection.profile_ids(teams)?;
+    if user_ids.is_empty() {
+        println!("No team selected, check DAUERZUSAGE_TEAMS_INCLUDE and DAUERZUSAGE_TEAMS_EXCLUDE");
+    }
 
-    // Vec<EnrichedHTMLEvent>
+    let mut filtered_training_ids = Vec::new();
 
-    let mut handled_training_ids = Vec::new();
+    for user_id in &user_ids {
+        let team = teams.iter().find(|team| team.profile_id == *user_id);
+        let event_filter = event_filters.for_team(user_id, team);
 
-    for user_id in user_ids {
-        client
-            .get(format!(
-                "https://www.spielerplus.de/site/switch-user?id={user_id}"
-            ))
-            .send()?;
+        spielerplus::switch_user(client, user_id)?;
 
-        let res = client.get(url).send()?;
+        let res = client.get(url).send_with_retry()?;
         let text = res.text()?;
         let document_events = scraper::Html::parse_document(&text);
 
@@ -253,10 +801,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .select(&title_selector)
             .map(|x| x.inner_html());
 
-        title = titles.next().ok_or("missing title")?;
+        title = titles
+            .next()
+            .ok_or_else(|| AutospielerError::markup("missing title"))?;
 
         if title != "Termine" && title != "Events" {
-            return Err(format!("title is not 'Termine' or 'Events', but '{}'", title).into());
+            return Err(AutospielerError::markup(format!(
+                "title is not 'Termine' or 'Events', but '{}'",
+                title
+            )));
         }
 
         let events = document_events.select(&event_selector);
@@ -266,38 +819,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let panel = event
                 .select(&panel_selector)
                 .next()
-                .ok_or("missing .panel")?;
+                .ok_or_else(|| AutospielerError::markup("missing .panel"))?;
             let heading_text = event
                 .select(&panel_heading_text_selector)
                 .next()
-                .ok_or("missing .panel-heading-text")?;
+                .ok_or_else(|| AutospielerError::markup("missing .panel-heading-text"))?;
             let event_title_html = heading_text
                 .select(&panel_title_selector)
                 .next()
-                .ok_or("missing .panel-title")?
+                .ok_or_else(|| AutospielerError::markup("missing .panel-title"))?
                 .inner_html();
 
             // set subtitle or default to empty string
             let event_subtitle_html = heading_text
                 .select(&panel_subtitle_selector)
                 .next()
-                .and_then(|v| Some(v.inner_html()))
-                .or(Some("".into()))
-                .unwrap();
+                .map(|v| v.inner_html())
+                .unwrap_or_default();
 
             let heading_info = event
                 .select(&panel_heading_info_selector)
                 .next()
-                .ok_or("missing .panel-heading-info")?;
+                .ok_or_else(|| AutospielerError::markup("missing .panel-heading-info"))?;
             let event_date_html = heading_info
                 .select(&panel_subtitle_selector)
                 .next()
-                .ok_or("missing .panel-subtitle")?
+                .ok_or_else(|| AutospielerError::markup("missing .panel-subtitle"))?
                 .inner_html();
             let widget_buttons = event
                 .select(&participation_widget_buttons_selector)
                 .next()
-                .ok_or("missing .participation-widget-buttons")?;
+                .ok_or_else(|| AutospielerError::markup("missing .participation-widget-buttons"))?;
             let event_time_values: Vec<String> = panel
                 .select(&panel_event_time_item_value_selector)
                 .map(|time| time.inner_html())
@@ -305,46 +857,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let event_start_ts = parse_sp_timestring(&event_time_values[0])
                 .or_else(|| parse_sp_timestring(&event_time_values[1]))
-                .ok_or("no event start found")?;
+                .ok_or_else(|| AutospielerError::markup("no event start found"))?;
 
             let event_type_parts = panel
                 .value()
                 .id()
-                .ok_or("no panel id found")?
+                .ok_or_else(|| AutospielerError::markup("no panel id found"))?
                 .split("-")
                 .collect::<Vec<_>>();
 
-            let event_type_sp = *event_type_parts
-                .get(1)
-                .ok_or("panel id is misformed (no event on position 1)")?;
+            let event_type_sp = *event_type_parts.get(1).ok_or_else(|| {
+                AutospielerError::markup("panel id is misformed (no event on position 1)")
+            })?;
 
             let training_id = event_type_parts
                 .get(2)
-                .ok_or("no id found in event id")?
+                .ok_or_else(|| AutospielerError::markup("no id found in event id"))?
                 .trim();
 
-            handled_training_ids.push(training_id.to_string());
-
             let mut override_end = true;
             let event_end_ts = parse_sp_timestring(&event_time_values[2])
                 .or_else(|| {
                     let dt = NaiveTime::parse_from_str(&event_start_ts, "%H:%M");
                     override_end = false;
 
-                    return match dt {
+                    match dt {
                         Ok(d) => Some((d + chrono::Duration::hours(2)).format("%H:%M").to_string()),
                         Err(err) => {
                             println!("{} {}", event_start_ts, err);
                             None
                         }
-                    };
+                    }
                 })
-                .ok_or("no end date found and start date is not valid")?;
+                .ok_or_else(|| {
+                    AutospielerError::markup("no end date found and start date is not valid")
+                })?;
 
             let (event_date_day_str, event_date_month_str) = event_date_html.trim().split_at(2);
             let event_date_month_str = &event_date_month_str[1..];
 
-            let event_date_month: u8 = event_date_month_str.parse()?;
+            let event_date_month: u8 = event_date_month_str.parse().map_err(|_| {
+                AutospielerError::markup(format!("invalid event date '{event_date_html}'"))
+            })?;
             let event_date_year: i32 = match last_month <= event_date_month.into() {
                 true => current_year,
                 false => current_year + 1,
@@ -359,114 +913,605 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 event_date_year, event_date_month_str, event_date_day_str, event_end_ts
             );
 
-            let team_extra = team_name_map
-                .get(user_id)
-                .and_then(|name| Some(format!(" – {name}")))
-                .or(Some("".into()))
-                .unwrap();
+            let team_extra = team
+                .map(|team| format!(" – {}", team.name))
+                .unwrap_or_default();
 
             println!(
                 "{}-{} {} ({})",
                 event_start_ts_iso, event_end_ts_iso, event_title_html, event_type_sp
             );
 
-            match outlook_events.get(training_id) {
-                Some(event) => {
-                    if !event.start.dateTime.starts_with(&event_start_ts_iso)
-                        || (!(event.end.dateTime.starts_with(&event_end_ts_iso)) && override_end)
+            let parse_iso = |iso: &str| {
+                NaiveDateTime::parse_from_str(iso, "%Y-%m-%dT%H:%M:%S").map_err(|_| {
+                    AutospielerError::markup(format!("invalid event date and time '{iso}'"))
+                })
+            };
+            let event_start = parse_iso(&event_start_ts_iso)?;
+            let event_end = parse_iso(&event_end_ts_iso)?;
+
+            let deadline = event.select(&deadline_selector).next().and_then(|element| {
+                spielerplus::parse_deadline(&element.text().collect::<String>(), event_start)
+            });
+            let holiday = holidays
+                .as_ref()
+                .and_then(|holidays| holidays.holiday(event_start.date()));
+            let filtered_event = filters::FilteredEvent {
+                kind: filters::EventKind::from_panel_type(event_type_sp),
+                title: event_title_html.trim(),
+                start: event_start,
+                holiday,
+            };
+            let today = current_date.date_naive();
+
+            let rejection =
+                event_filter.and_then(|filter| filter.rejection(&filtered_event, today));
+            if let Some(rejection) = rejection {
+                println!("Skipping {training_id}: {rejection}");
+                filtered_training_ids.push(training_id.to_string());
+                continue;
+            }
+
+            handled_training_ids.push(training_id.to_string());
+
+            let selected_attendance = selected_attendance(&widget_buttons, &selected_selector);
+            let locked = is_participation_locked(&widget_buttons)
+                || deadline.is_some_and(|deadline| deadline <= now);
+            spielerplus_events.insert(
+                training_id.to_owned(),
+                SpielerplusEvent {
+                    user_id: user_id.clone(),
+                    event_type: event_type_sp.to_owned(),
+                    attendance: selected_attendance.as_ref().ok().copied().flatten(),
+                    locked,
+                    holiday,
+                },
+            );
+
+            let calendar_event = calendar_events.get(training_id);
+            // the attendee answered in the calendar, but Spielerplus doesn't take it anymore
+            let answer_locked = locked
+                && calendar_event
+                    .and_then(|event| event.attendance)
+                    .is_some_and(|answer| {
+                        selected_attendance
+                            .as_ref()
+                            .is_ok_and(|selected| *selected != Some(answer))
+                    });
+
+            // Which side wins when both changed is up to the conflict policy, see
+            // `resolve_answer` for who else answers. The calendar then shows the answer
+            // Spielerplus ends up with.
+            let spielerplus_attendance = match selected_attendance {
+                Ok(selected) => {
+                    let resolved = match calendar_event {
+                        Some(event) => attendance.resolve(
+                            training_id,
+                            event.attendance,
+                            event.response_time,
+                            selected,
+                        ),
+                        None => selected,
+                    };
+                    let answered_event = answers::AnsweredEvent {
+                        profile_id: user_id,
+                        team,
+                        event: &filtered_event,
+                        end: event_end,
+                        deadline,
+                    };
+                    let declined_for_absence = attendance.declined_for_absence(training_id);
+                    let state = answers::AnswerState {
+                        resolved,
+                        declined_for_absence,
+                        answer_before_absence: attendance.answer_before_absence(training_id),
+                    };
+                    let busy_times = || {
+                        if busy_times_failed {
+                            return None;
+                        }
+                        calendar
+                            .busy_times(event_start, event_end)
+                            .map_err(|err| {
+                                eprintln!("could not check for conflicting appointments: {err}");
+                                busy_times_failed = true;
+                            })
+                            .ok()
+                    };
+                    let answer = answers::resolve_answer(
+                        &answered_event,
+                        state,
+                        &answerers,
+                        busy_times,
+                        today,
+                        now,
+                    );
+                    if let Some(answers::Answer {
+                        attendance: answer,
+                        source,
+                        reason,
+                    }) = &answer
                     {
-                        update_event_time(
-                            &outlook_user_principal_name,
-                            &outlook_calendar_id,
-                            &microsoft_token,
-                            &event.id,
-                            &event_start_ts_iso,
-                            &event_end_ts_iso,
-                        )?;
+                        match source {
+                            AnswerSource::Synced => {}
+                            AnswerSource::Absence => {
+                                println!("Declining {training_id}, absent: {reason}")
+                            }
+                            AnswerSource::AbsenceEnded => println!(
+                                "Answering {training_id} with {answer:?}, it's not during an absence anymore"
+                            ),
+                            AnswerSource::Conflict(subject) => println!(
+                                "Answering {training_id} with {answer:?}, it conflicts with {}",
+                                subject.as_deref().unwrap_or("an appointment")
+                            ),
+                            AnswerSource::Rule => {
+                                println!("Answering {training_id} with {answer:?} by rule")
+                            }
+                            AnswerSource::Deadline(deadline) => println!(
+                                "Answering {training_id} with {answer:?}, its deadline {deadline} is close"
+                            ),
+                        }
                     }
-
-                    let outlook_event_attendence = event
-                        .attendees
-                        .last()
-                        .ok_or("no attendees found")?
-                        .status
-                        .response
-                        .as_str();
-                    if outlook_event_attendence == "none" {
-                    } else {
-                        let new_attendance = match outlook_event_attendence {
-                            "accepted" => Attendance::ACCEPTED,
-                            "declined" => Attendance::DECLINED,
-                            "tentativelyAccepted" | _ => Attendance::UNSURE,
-                        };
-
-                        let needs_update = match widget_buttons.select(&selected_selector).next() {
-                            Some(selected_button) => {
-                                let previous_attendance = match selected_button
-                                    .value()
-                                    .attr("title")
-                                    .ok_or("missing selected_button attr 'title'")?
+                    let answer_before = resolved;
+                    let resolved = answer.as_ref().map(|answer| answer.attendance);
+                    let reason = answer.as_ref().map_or("-", |answer| answer.reason);
+                    let declining_for_absence = answer
+                        .as_ref()
+                        .is_some_and(|answer| answer.source == AnswerSource::Absence);
+
+                    let new_attendance = resolved.filter(|resolved| selected != Some(*resolved));
+                    let mut rejected = false;
+                    if let Some(new_attendance) = new_attendance.filter(|_| read_only) {
+                        println!("Not answering {training_id} with {new_attendance:?}, the calendar is read-only");
+                    } else if let Some(new_attendance) = new_attendance.filter(|_| locked) {
+                        println!("Not answering {training_id} with {new_attendance:?}, its participation is locked");
+                    } else if let Some(new_attendance) = new_attendance {
+                        match set_attendence(
+                            client,
+                            user_id,
+                            training_id,
+                            event_type_sp,
+                            reason,
+                            new_attendance,
+                        ) {
+                            Ok(()) => {
+                                attendance.set_spielerplus(training_id, new_attendance);
+
+                                if let Some(spielerplus_event) =
+                                    spielerplus_events.get_mut(training_id)
                                 {
-                                    "Zugesagt" | "Confirmed" => Attendance::ACCEPTED,
-                                    "Unsicher" | "Unsure" => Attendance::UNSURE,
-                                    "Absagen / Abwesend" | "Declined / Absent" => {
-                                        Attendance::DECLINED
-                                    }
-                                    other => {
-                                        return Err(format!(
-                                            "Unknown selected Zusage button title '{other}'"
-                                        )
-                                        .into());
-                                    }
-                                };
-
-                                new_attendance != previous_attendance
+                                    spielerplus_event.attendance = Some(new_attendance);
+                                }
                             }
-                            None => true,
-                        };
-
-                        if needs_update {
-                            set_attendence(
-                                &client,
-                                &user_id,
-                                &training_id.to_string(),
-                                event_type_sp,
-                                "-",
-                                new_attendance,
-                            )?;
+                            Err(
+                                err @ (AutospielerError::ParticipationRejected { .. }
+                                | AutospielerError::ParticipationUnverified { .. }),
+                            ) => {
+                                eprintln!("{err}");
+                                rejected = true;
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    if read_only || locked || rejected {
+                        // Spielerplus keeps its answer
+                        Some(selected)
+                    } else {
+                        if declining_for_absence {
+                            attendance.decline_for_absence(training_id, answer_before);
+                        } else if declined_for_absence && absences.reason_at(event_start).is_none()
+                        {
+                            attendance.absence_ended(training_id);
+                        }
+                        Some(resolved)
+                    }
+                }
+                Err(err) if calendar_event.is_some_and(|event| event.attendance.is_some()) => {
+                    return Err(err)
+                }
+                Err(_) => None,
+            };
+
+            let reminder = deadline
+                .and_then(|deadline| Some((deadline, deadlines.reminder_due(deadline, now)?)));
+            if let Some((deadline, reminders)) = reminder.filter(|_| {
+                spielerplus_attendance == Some(None)
+                    && !locked
+                    && !attendance.reminder_sent(training_id)
+            }) {
+                let text = format!(
+                    "Please answer {} on {} in Spielerplus or in your calendar, the deadline is {deadline}.",
+                    event_title_html.trim(),
+                    event_start.format("%d.%m.%Y %H:%M")
+                );
+                match reminders.send(user_mail, event_title_html.trim(), text) {
+                    Ok(()) => {
+                        println!("Reminded {user_mail} of the deadline of {training_id}");
+                        attendance.set_reminder_sent(training_id);
+                    }
+                    Err(err) => eprintln!("could not send the reminder for {training_id}: {err}"),
+                }
+            }
+
+            match calendar_event {
+                Some(event) => {
+                    calendar_changes.extend(time_change(
+                        event,
+                        event_start,
+                        event_end,
+                        override_end,
+                    ));
+
+                    attendance.created(training_id);
+
+                    if answer_locked && !attendance.locked_noted(training_id) {
+                        locked_notes.push((calendar_changes.len(), training_id.to_owned()));
+                        calendar_changes.push(CalendarChange::UpdateBody {
+                            event_id: event.id.clone(),
+                            body: event_body(holiday, true),
+                        });
+                    }
+
+                    if let Some(spielerplus_attendance) = spielerplus_attendance {
+                        let (shown_attendance, subject) =
+                            calendar::attendance_from_subject(&event.subject);
+                        if shown_attendance != spielerplus_attendance {
+                            calendar_changes.push(CalendarChange::ShowSpielerplusAttendance {
+                                event_id: event.id.clone(),
+                                subject: calendar::subject_with_attendance(
+                                    subject,
+                                    spielerplus_attendance,
+                                ),
+                                attendance: spielerplus_attendance,
+                            });
                         }
                     }
                 }
                 None => {
-                    office::create_outlook_event(
-                        &outlook_user_principal_name,
-                        &outlook_calendar_id,
-                        &microsoft_token,
-                        &format!("{}{}", event_title_html.trim(), &team_extra),
-                        "New training found in Spielerplus. Please accept/decline this event.",
-                        &event_start_ts_iso,
-                        &event_end_ts_iso,
-                        &event_subtitle_html.trim(),
-                        &user_mail,
-                        training_id,
-                    )?;
+                    calendar_changes.push(CalendarChange::Create(calendar::NewCalendarEvent {
+                        subject: calendar::subject_with_attendance(
+                            &format!("{}{}", event_title_html.trim(), &team_extra),
+                            spielerplus_attendance.flatten(),
+                        ),
+                        body: event_body(holiday, false),
+                        start: event_start,
+                        end: event_end,
+                        location: event_subtitle_html.trim().to_owned(),
+                        spielerplus_id: training_id.to_owned(),
+                        first_attempt: Some(attendance.create_attempt(training_id)),
+                    }));
                 }
             }
         }
     }
 
-    for event in outlook_events {
-        if handled_training_ids.contains(&event.0) {
-            continue;
+    calendar_changes.extend(cancellations(
+        calendar_events,
+        &handled_training_ids,
+        &filtered_training_ids,
+    ));
+
+    attendance.retain(&handled_training_ids.iter().map(String::as_str).collect());
+    attendance.save()?;
+
+    let results = calendar.apply_changes(&calendar_changes);
+
+    // the note is only there once its change went through, otherwise it's tried again
+    for (index, training_id) in &locked_notes {
+        if results.get(*index).is_some_and(Result::is_ok) {
+            attendance.set_locked_noted(training_id);
+        }
+    }
+    attendance.save()?;
+
+    let mut failed_changes = 0;
+    for (change, result) in calendar_changes.iter().zip(results) {
+        if let Err(err) = result {
+            eprintln!("could not {}: {err}", change.description());
+            failed_changes += 1;
         }
-        println!("didn't handle {}, deleting...", event.0);
-        cancel_event(
-            &outlook_user_principal_name,
-            &outlook_calendar_id,
-            &microsoft_token,
-            &event.1.id,
-        )?;
+    }
+
+    if failed_changes > 0 {
+        return Err(AutospielerError::PartialSync {
+            failed: failed_changes,
+            total: calendar_changes.len(),
+        });
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widget_locked(html: &str) -> bool {
+        let fragment = scraper::Html::parse_fragment(html);
+        let selector = scraper::Selector::parse(".participation-widget-buttons").unwrap();
+        is_participation_locked(&fragment.select(&selector).next().unwrap())
+    }
+
+    #[test]
+    fn reads_an_open_participation() {
+        assert!(!widget_locked(
+            r#"<div class="participation-widget-buttons">
+                <button class="participation-button selected" title="Zugesagt"></button>
+                <button class="participation-button" title="Unsicher"></button>
+                <button class="participation-button" title="Absagen / Abwesend"></button>
+            </div>"#
+        ));
+        // only one answer can't be given, e.g. accepting a full event
+        assert!(!widget_locked(
+            r#"<div class="participation-widget-buttons">
+                <button class="participation-button" title="Zugesagt" disabled></button>
+                <button class="participation-button" title="Unsicher"></button>
+                <button class="participation-button" title="Absagen / Abwesend"></button>
+            </div>"#
+        ));
+    }
+
+    #[test]
+    fn reads_a_locked_participation() {
+        assert!(widget_locked(
+            r#"<div class="participation-widget-buttons">
+                <button class="participation-button selected" title="Zugesagt" disabled></button>
+                <button class="participation-button" title="Unsicher" disabled></button>
+                <button class="participation-button" title="Absagen / Abwesend" disabled></button>
+            </div>"#
+        ));
+        assert!(widget_locked(
+            r#"<div class="participation-widget-buttons locked">
+                <button class="participation-button selected" title="Zugesagt"></button>
+                <button class="participation-button" title="Absagen / Abwesend"></button>
+            </div>"#
+        ));
+        assert!(widget_locked(
+            r#"<div class="participation-widget-buttons">
+                <a class="participation-button selected disabled" title="Zugesagt"></a>
+                <a class="participation-button read-only" title="Absagen / Abwesend"></a>
+            </div>"#
+        ));
+    }
+
+    #[test]
+    fn reads_an_empty_widget_as_open() {
+        assert!(!widget_locked(
+            r#"<div class="participation-widget-buttons"></div>"#
+        ));
+    }
+
+    /// The participation form with `selected` as the selected button.
+    fn participation_form(selected: &str) -> String {
+        ["Zugesagt", "Unsicher", "Absagen / Abwesend"]
+            .map(|title| {
+                let class = if title == selected { " selected" } else { "" };
+                format!(r#"<button class="participation-button{class}" title="{title}"></button>"#)
+            })
+            .join("")
+    }
+
+    #[test]
+    fn reads_a_successful_participation_response() {
+        assert_eq!(
+            participation_response(r#"{"success": true}"#, Attendance::Accepted).unwrap(),
+            ParticipationResponse::Unverified
+        );
+
+        let html = format!(
+            r#"<div class="participation-widget-buttons">{}</div>"#,
+            participation_form("Zugesagt")
+        );
+        let body = serde_json::json!({ "success": true, "html": html }).to_string();
+        assert_eq!(
+            participation_response(&body, Attendance::Accepted).unwrap(),
+            ParticipationResponse::Applied
+        );
+    }
+
+    #[test]
+    fn reads_json_errors() {
+        let body = r#"{
+            "success": false,
+            "errors": {
+                "participation-reason": ["Bitte gib einen Grund an."],
+                "participation": {"deadline": "Die Frist ist abgelaufen."}
+            }
+        }"#;
+        assert_eq!(
+            participation_response(body, Attendance::Declined).unwrap(),
+            // by field name, as serde_json sorts the fields
+            ParticipationResponse::Rejected(vec![
+                "Die Frist ist abgelaufen.".to_owned(),
+                "Bitte gib einen Grund an.".to_owned(),
+            ])
+        );
+
+        assert_eq!(
+            participation_response(r#"{"status": "error"}"#, Attendance::Declined).unwrap(),
+            ParticipationResponse::Rejected(vec!["no reason given".to_owned()])
+        );
+    }
+
+    #[test]
+    fn reads_the_errors_of_an_html_form() {
+        let body = r#"
+            <form id="participation-form">
+                <div class="form-group field-participation-reason required has-error">
+                    <textarea name="Participation[reason]"></textarea>
+                    <div class="help-block">
+                        Grund darf nicht leer sein.
+                    </div>
+                </div>
+            </form>"#;
+        assert_eq!(
+            participation_response(body, Attendance::Declined).unwrap(),
+            ParticipationResponse::Rejected(vec!["Grund darf nicht leer sein.".to_owned()])
+        );
+    }
+
+    #[test]
+    fn reads_a_participation_that_wasnt_applied() {
+        let body = format!(
+            r#"<div class="participation-widget-buttons">{}</div>"#,
+            participation_form("Unsicher")
+        );
+        assert_eq!(
+            participation_response(&body, Attendance::Declined).unwrap(),
+            ParticipationResponse::Selected(Some(Attendance::Unsure))
+        );
+
+        let body = format!(
+            r#"<div class="participation-widget-buttons">{}</div>"#,
+            participation_form("")
+        );
+        assert_eq!(
+            participation_response(&body, Attendance::Declined).unwrap(),
+            ParticipationResponse::Selected(None)
+        );
+    }
+
+    #[test]
+    fn fails_on_a_response_that_doesnt_show_the_answer() {
+        let err = verify_participation(r#"{"success": true}"#, "12345", Attendance::Accepted)
+            .unwrap_err();
+        assert!(
+            matches!(err, AutospielerError::ParticipationUnverified { ref event_id } if event_id == "12345"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn fails_on_the_login_page() {
+        let body = r#"<!DOCTYPE html>
+            <html>
+            <head><title>Einloggen</title></head>
+            <body>
+                <form id="login-form" action="/site/login" method="post">
+                    <input type="hidden" name="_csrf" value="abc">
+                    <input type="email" name="LoginForm[email]">
+                    <input type="password" name="LoginForm[password]">
+                </form>
+            </body>
+            </html>"#;
+
+        assert_eq!(
+            participation_response(body, Attendance::Accepted).unwrap(),
+            ParticipationResponse::LoggedOut
+        );
+        let err = verify_participation(body, "12345", Attendance::Accepted).unwrap_err();
+        assert!(matches!(err, AutospielerError::Authentication(_)), "{err}");
+    }
+
+    #[test]
+    fn accepts_a_response_showing_the_answer() {
+        let body = format!(
+            r#"<div class="participation-widget-buttons">{}</div>"#,
+            participation_form("Absagen / Abwesend")
+        );
+        assert!(verify_participation(&body, "12345", Attendance::Declined).is_ok());
+    }
+
+    fn new_event(spielerplus_id: &str, start: &str) -> calendar::NewCalendarEvent {
+        let start = NaiveDateTime::parse_from_str(start, "%Y-%m-%d %H:%M").unwrap();
+        calendar::NewCalendarEvent {
+            subject: "Training".to_owned(),
+            body: event_body(None, false),
+            start,
+            end: start + chrono::Duration::hours(2),
+            location: String::new(),
+            spielerplus_id: spielerplus_id.to_owned(),
+            first_attempt: None,
+        }
+    }
+
+    #[test]
+    fn applies_the_event_diff_to_the_calendar() {
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let mut calendar = calendar::InMemoryBackend::default();
+        let created = calendar.apply_changes(&[
+            CalendarChange::Create(new_event("42", "2024-05-02 19:00")),
+            CalendarChange::Create(new_event("43", "2024-05-09 19:00")),
+            CalendarChange::Create(new_event("44", "2024-05-16 19:00")),
+        ]);
+        assert!(created.iter().all(Result::is_ok));
+        let calendar_events = calendar.list_events(today).unwrap();
+
+        // 42 moved, 43 is unchanged, 44 is gone and 45 is new in Spielerplus
+        let moved = new_event("42", "2024-05-02 20:00");
+        let unchanged = new_event("43", "2024-05-09 19:00");
+        let mut changes = Vec::new();
+        changes.extend(time_change(
+            &calendar_events["42"],
+            moved.start,
+            moved.end,
+            true,
+        ));
+        changes.extend(time_change(
+            &calendar_events["43"],
+            unchanged.start,
+            unchanged.end,
+            true,
+        ));
+        changes.push(CalendarChange::Create(new_event("45", "2024-05-23 19:00")));
+        changes.extend(cancellations(
+            calendar_events,
+            &["42".to_owned(), "43".to_owned(), "45".to_owned()],
+            &[],
+        ));
+
+        let descriptions: Vec<_> = changes.iter().map(CalendarChange::description).collect();
+        assert_eq!(
+            descriptions,
+            [
+                "update event memory-1",
+                "create event 45",
+                "cancel event memory-3"
+            ]
+        );
+        assert!(calendar.apply_changes(&changes).iter().all(Result::is_ok));
+
+        let calendar_events = calendar.list_events(today).unwrap();
+        let mut spielerplus_ids: Vec<_> = calendar_events.keys().cloned().collect();
+        spielerplus_ids.sort();
+        assert_eq!(spielerplus_ids, ["42", "43", "45"]);
+        assert_eq!(calendar_events["42"].start, moved.start);
+        assert_eq!(calendar_events["42"].end, moved.end);
+        assert_eq!(calendar_events["43"].start, unchanged.start);
+    }
+
+    #[test]
+    fn keeps_the_end_if_spielerplus_doesnt_show_one() {
+        let mut calendar = calendar::InMemoryBackend::default();
+        let event = new_event("42", "2024-05-02 19:00");
+        calendar
+            .apply_changes(&[CalendarChange::Create(new_event("42", "2024-05-02 19:00"))])
+            .into_iter()
+            .for_each(|result| result.unwrap());
+        let calendar_events = calendar.list_events(event.start.date()).unwrap();
+
+        let made_up_end = event.end + chrono::Duration::hours(1);
+        assert!(time_change(&calendar_events["42"], event.start, made_up_end, false).is_none());
+        assert!(time_change(&calendar_events["42"], event.start, made_up_end, true).is_some());
+    }
+
+    #[test]
+    fn cancels_filtered_out_events() {
+        let mut calendar = calendar::InMemoryBackend::default();
+        let event = new_event("42", "2024-05-02 19:00");
+        let today = event.start.date();
+        calendar
+            .apply_changes(&[CalendarChange::Create(event)])
+            .into_iter()
+            .for_each(|result| result.unwrap());
+
+        let changes = cancellations(
+            calendar.list_events(today).unwrap(),
+            &[],
+            &["42".to_owned()],
+        );
+        assert!(calendar.apply_changes(&changes).iter().all(Result::is_ok));
+
+        assert!(calendar.list_events(today).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{error::AutospielerError, read_env, read_optional_env};
+use std::{
+    error::Error,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+pub struct NotificationConfig {
+    pub listen_address: String,
+    /// Public URL Graph should post the notifications to. Without it the listener still
+    /// runs, but no subscription is created, so notifications can be posted by hand.
+    pub notification_url: Option<String>,
+    /// Shared secret Graph sends along with every notification.
+    pub client_state: String,
+}
+
+impl NotificationConfig {
+    pub fn from_env() -> Result<Option<Self>, AutospielerError> {
+        let Some(listen_address) = read_optional_env("NOTIFICATION_LISTEN_ADDRESS")? else {
+            return Ok(None);
+        };
+
+        Ok(Some(NotificationConfig {
+            listen_address,
+            notification_url: read_optional_env("NOTIFICATION_URL")?,
+            client_state: read_env("NOTIFICATION_CLIENT_STATE")?,
+        }))
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeNotificationResourceData {
+    id: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeNotification {
+    client_state: Option<String>,
+    resource_data: Option<ChangeNotificationResourceData>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChangeNotificationCollection {
+    value: Vec<ChangeNotification>,
+}
+
+/// Starts the HTTP listener on a background thread. The ids of the Outlook events
+/// Graph reports as changed are sent to the returned channel.
+pub fn start_listener(config: &NotificationConfig) -> Result<Receiver<String>, AutospielerError> {
+    let server = Server::http(&config.listen_address).map_err(|e| {
+        AutospielerError::config(format!(
+            "could not listen on {}: {e}",
+            config.listen_address
+        ))
+    })?;
+    let client_state = config.client_state.clone();
+    let (sender, receiver) = mpsc::channel();
+
+    println!(
+        "Listening for change notifications on {}",
+        config.listen_address
+    );
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if let Err(err) = handle_request(request, &client_state, &sender) {
+                eprintln!("could not handle change notification: {err}");
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn handle_request(
+    mut request: Request,
+    client_state: &str,
+    sender: &Sender<String>,
+) -> Result<(), Box<dyn Error>> {
+    if request.method() != &Method::Post {
+        request.respond(Response::empty(405))?;
+        return Ok(());
+    }
+
+    // Graph validates the notification URL by posting a token that has to be echoed back.
+    let url = reqwest::Url::parse(&format!("http://localhost{}", request.url()))?;
+    if let Some((_, validation_token)) = url.query_pairs().find(|(key, _)| key == "validationToken")
+    {
+        let content_type = Header::from_bytes("Content-Type", "text/plain").unwrap();
+        request.respond(Response::from_string(validation_token).with_header(content_type))?;
+        return Ok(());
+    }
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+
+    let notifications = match serde_json::from_str::<ChangeNotificationCollection>(&body) {
+        Ok(notifications) => notifications,
+        Err(err) => {
+            request.respond(Response::empty(400))?;
+            return Err(format!("invalid change notification body: {err}").into());
+        }
+    };
+
+    // Graph expects an answer within a few seconds, the actual work happens on the daemon thread.
+    request.respond(Response::empty(202))?;
+
+    for notification in notifications.value {
+        if notification.client_state.as_deref() != Some(client_state) {
+            log::warn!("Ignoring change notification with unexpected clientState");
+            continue;
+        }
+
+        if let Some(resource_data) = notification.resource_data {
+            sender.send(resource_data.id)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::blocking::Client;
+
+    /// Posts `body` to `path` on a local listener and passes the request to `handle_request`.
+    fn post(path: &str, body: &str) -> (reqwest::StatusCode, String, Vec<String>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}{path}", server.server_addr().to_ip().unwrap());
+        let body = body.to_owned();
+        let client = thread::spawn(move || {
+            let response = Client::new().post(url).body(body).send().unwrap();
+            (response.status(), response.text().unwrap())
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        handle_request(server.recv().unwrap(), "secret", &sender).unwrap();
+        let (status, text) = client.join().unwrap();
+        (status, text, receiver.try_iter().collect())
+    }
+
+    #[test]
+    fn echoes_the_validation_token() {
+        let (status, text, ids) = post("/?validationToken=Validation%3A%20Token", "");
+
+        assert_eq!(status, 200);
+        assert_eq!(text, "Validation: Token");
+        assert!(ids.is_empty());
+    }
+
+    #[test]
+    fn ignores_notifications_with_another_client_state() {
+        let (status, _, ids) = post(
+            "/",
+            r#"{"value": [
+                {"clientState": "wrong", "resourceData": {"id": "AAMkAD1"}},
+                {"resourceData": {"id": "AAMkAD2"}},
+                {"clientState": "secret", "resourceData": {"id": "AAMkAD3"}}
+            ]}"#,
+        );
+
+        assert_eq!(status, 202);
+        assert_eq!(ids, ["AAMkAD3"]);
+    }
+
+    #[test]
+    fn rejects_invalid_bodies() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr().to_ip().unwrap());
+        let client = thread::spawn(move || Client::new().post(url).body("{").send().unwrap());
+
+        let (sender, _receiver) = mpsc::channel();
+        assert!(handle_request(server.recv().unwrap(), "secret", &sender).is_err());
+        assert_eq!(client.join().unwrap().status(), 400);
+    }
+}
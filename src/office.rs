@@ -1,250 +1,2127 @@
-use std::{collections::HashMap, error::Error};
+use crate::{
+    calendar::{
+        Attendance, BusyTime, CalendarBackend, CalendarChange, CalendarEvent, CalendarEventMap,
+        NewCalendarEvent, OutOfOffice,
+    },
+    error::AutospielerError,
+    local_time::{zone_to_local, LOCAL_TIME_ZONE},
+    retry::SendWithRetry,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_GRAPH_BASE_URL: &str = "https://graph.microsoft.com/v1.0";
+const LOGIN_BASE_URL: &str = "https://login.microsoftonline.com";
+const SP_ID_PROPERTY: &str = "String {78ba6e36-6d32-4157-83aa-e36c77df0418} Name SP_ID";
+const SP_USER_EMAIL_PROPERTY: &str =
+    "String {abdd660a-9ce1-4aa3-b4e7-eca89ccfedea} Name SP_USER_EMAIL";
+const TIME_ZONE: &str = "Europe/Zurich";
+const PREFER_TIME_ZONE: &str = "outlook.timezone=\"Europe/Zurich\"";
+const EVENT_SELECT: &str = "id, subject, singleValueExtendedProperties, start, end, attendees";
+
+#[derive(Debug)]
+pub enum GraphError {
+    /// Requesting an access token failed, e.g. because the Entra credentials are wrong.
+    Authentication {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    /// Graph answered with an error response.
+    Api {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    /// The request couldn't be sent or the response couldn't be read.
+    Network(reqwest::Error),
+    /// Graph answered with something that doesn't match the expected model.
+    InvalidResponse(String),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Authentication {
+                status,
+                code,
+                message,
+            } => write!(
+                f,
+                "token request failed with status code {status}: {code}: {message}"
+            ),
+            GraphError::Api {
+                status,
+                code,
+                message,
+            } => write!(
+                f,
+                "Graph request failed with status code {status}: {code}: {message}"
+            ),
+            GraphError::Network(err) => write!(f, "Graph request failed: {err}"),
+            GraphError::InvalidResponse(message) => {
+                write!(f, "unexpected Graph response: {message}")
+            }
+        }
+    }
+}
+
+impl Error for GraphError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GraphError::Network(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GraphError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            GraphError::InvalidResponse(err.to_string())
+        } else {
+            GraphError::Network(err)
+        }
+    }
+}
+
+impl From<serde_json::Error> for GraphError {
+    fn from(err: serde_json::Error) -> Self {
+        GraphError::InvalidResponse(err.to_string())
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct MicrosoftErrorDetail {
+    code: String,
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct MicrosoftErrorResponse {
+    error: MicrosoftErrorDetail,
+}
+
+impl GraphError {
+    fn from_body(status: u16, body: &str) -> Self {
+        match serde_json::from_str::<MicrosoftErrorResponse>(body) {
+            Ok(data) => GraphError::Api {
+                status,
+                code: data.error.code,
+                message: data.error.message,
+            },
+            Err(_) => GraphError::Api {
+                status,
+                code: String::new(),
+                message: body.to_owned(),
+            },
+        }
+    }
+
+    fn from_response(response: Response) -> Self {
+        let status = response.status().as_u16();
+        match response.text() {
+            Ok(body) => GraphError::from_body(status, &body),
+            Err(err) => err.into(),
+        }
+    }
+
+    /// The Graph error code, e.g. `ErrorItemNotFound`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            GraphError::Authentication { code, .. } | GraphError::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the response if it has the `expected` status and the Graph error otherwise.
+fn expect_status(response: Response, expected: u16) -> Result<Response, GraphError> {
+    if response.status().as_u16() == expected {
+        Ok(response)
+    } else {
+        Err(GraphError::from_response(response))
+    }
+}
 
 #[derive(serde::Deserialize)]
 struct MicrosoftTokenResponse {
     access_token: String,
+    expires_in: u64,
 }
 
-pub fn get_microsoft_token<'a>(
-    client_id: &str,
-    client_secret: &str,
-    tenant_id: &str,
-) -> Result<String, Box<dyn Error>> {
-    let response = reqwest::blocking::Client::new()
-        .post(format!(
-            "https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token",
-        ))
-        .form(&[
-            ("grant_type", "client_credentials"),
-            ("scope", "https://graph.microsoft.com/.default"),
-        ])
-        .basic_auth(client_id, Some(client_secret))
-        .send()?;
-
-    let response_status = response.status().as_u16();
-    if response_status != 200 {
-        let response_text = response.text()?;
-        return Err(
-            format!("request failed with status code {response_status}\n{response_text}").into(),
-        );
+#[derive(serde::Deserialize)]
+struct MicrosoftTokenErrorResponse {
+    error: String,
+    error_description: String,
+}
+
+pub struct MicrosoftToken {
+    pub access_token: String,
+    pub expires_at: Instant,
+}
+
+pub trait TokenProvider {
+    fn access_token(&mut self) -> Result<String, GraphError>;
+}
+
+/// Requests client credentials tokens from Entra and keeps them around until shortly
+/// before they expire, so long running processes don't request one for every sync.
+pub struct MicrosoftTokenCache {
+    client: Client,
+    login_base_url: String,
+    client_id: String,
+    client_secret: String,
+    tenant_id: String,
+    token: Option<MicrosoftToken>,
+}
+
+impl MicrosoftTokenCache {
+    pub fn new(client: Client, client_id: &str, client_secret: &str, tenant_id: &str) -> Self {
+        MicrosoftTokenCache {
+            client,
+            login_base_url: LOGIN_BASE_URL.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            tenant_id: tenant_id.to_owned(),
+            token: None,
+        }
     }
 
-    let data: MicrosoftTokenResponse = response.json::<MicrosoftTokenResponse>()?;
-    Ok(data.access_token)
+    fn request_token(&self) -> Result<MicrosoftToken, GraphError> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/{}/oauth2/v2.0/token",
+                self.login_base_url, self.tenant_id
+            ))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("scope", "https://graph.microsoft.com/.default"),
+            ])
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .send_with_retry()?;
+
+        let status = response.status().as_u16();
+        if status != 200 {
+            let body = response.text()?;
+            return Err(
+                match serde_json::from_str::<MicrosoftTokenErrorResponse>(&body) {
+                    Ok(data) => GraphError::Authentication {
+                        status,
+                        code: data.error,
+                        message: data.error_description,
+                    },
+                    Err(_) => GraphError::Authentication {
+                        status,
+                        code: String::new(),
+                        message: body,
+                    },
+                },
+            );
+        }
+
+        let data = response.json::<MicrosoftTokenResponse>()?;
+        Ok(MicrosoftToken {
+            access_token: data.access_token,
+            expires_at: Instant::now() + Duration::from_secs(data.expires_in),
+        })
+    }
+}
+
+impl TokenProvider for MicrosoftTokenCache {
+    fn access_token(&mut self) -> Result<String, GraphError> {
+        let needs_refresh = match &self.token {
+            Some(token) => token.expires_at <= Instant::now() + Duration::from_secs(5 * 60),
+            None => true,
+        };
+
+        if needs_refresh {
+            self.token = Some(self.request_token()?);
+        }
+
+        Ok(self.token.as_ref().unwrap().access_token.clone())
+    }
 }
 
-pub fn create_outlook_event(
+/// Namespace for the `transactionId`s of created events, so the same Spielerplus event
+/// always results in the same id.
+const TRANSACTION_ID_NAMESPACE: uuid::Uuid = uuid::uuid!("5b0c7f0e-3c52-4f7e-9a57-8d7f0b9d3c21");
+
+/// Deterministic `transactionId` for the Outlook event of a Spielerplus event. Graph
+/// ignores a second create with the same id, so creating an event can safely be retried.
+/// `first_attempt` tells a retry apart from creating a deleted event again.
+fn transaction_id(
     user_principal_name: &str,
     calendar_id: &str,
-    access_token: &str,
-    event_name: &str,
-    body: &str,
-    start_datetime: &str,
-    end_datetime: &str,
-    location_name: &str,
     email_address: &str,
     spielerplus_id: &str,
-) -> Result<serde_json::Value, Box<dyn Error>> {
-    let response = reqwest::blocking::Client::new()
-        .post(format!(
-            "https://graph.microsoft.com/v1.0/users/{}/calendars/{}/events",
-            user_principal_name, calendar_id,
-        ))
-        .header("content-type", "application/json")
-        .body(
-            serde_json::json!({
-                "subject": event_name,
-                "body": {
-                    "contentType": "text",
-                    "content": body
-                },
-                "start": {
-                    "dateTime": start_datetime,
-                    "timeZone": "Europe/Zurich"
-                },
-                "end": {
-                    "dateTime": end_datetime,
-                    "timeZone": "Europe/Zurich"
-                },
-                "location": {
-                    "displayName": location_name
-                },
-                "singleValueExtendedProperties": [
-                    {
-                        "id": "String {78ba6e36-6d32-4157-83aa-e36c77df0418} Name SP_ID",
-                        "value": spielerplus_id,
-                    },
-                    {
-                        "id": "String {abdd660a-9ce1-4aa3-b4e7-eca89ccfedea} Name SP_USER_EMAIL",
-                        "value": email_address,
-                    },
-                ],
-                "attendees": [
-                    {
-                        "emailAddress": {
-                            "address": email_address,
-                            "name": email_address
-                        },
-                        "type": "required"
-                    }
-                ]
-            })
-            .to_string(),
-        )
-        .bearer_auth(access_token)
-        .send()?;
-
-    if response.status() != reqwest::StatusCode::from_u16(201).unwrap() {
-        return Err(format!("request failed with status code {}", response.status()).into());
+    first_attempt: Option<DateTime<Utc>>,
+) -> String {
+    let mut name = format!("{user_principal_name}/{calendar_id}/{email_address}/{spielerplus_id}");
+    if let Some(first_attempt) = first_attempt {
+        name.push_str(&format!("/{}", first_attempt.timestamp_millis()));
     }
-
-    Ok(response.json()?)
+    uuid::Uuid::new_v5(&TRANSACTION_ID_NAMESPACE, name.as_bytes()).to_string()
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct MicrosoftGetEventsResponseEventTimestamp {
-    pub dateTime: String,
-    pub timeZone: String,
+    pub date_time: String,
+    pub time_zone: String,
 }
 
-#[derive(serde::Deserialize)]
+impl MicrosoftGetEventsResponseEventTimestamp {
+    fn local(date_time: &str) -> Self {
+        MicrosoftGetEventsResponseEventTimestamp {
+            date_time: date_time.to_owned(),
+            time_zone: TIME_ZONE.to_owned(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct MicrosoftGetEventsResponseEventAttendeeStatus {
     pub response: String,
     pub time: String,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct MicrosoftGetEventsResponseEventAttendee {
     pub r#type: String,
     pub status: MicrosoftGetEventsResponseEventAttendeeStatus,
 }
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct SingleValueExtendedProperties {
     pub id: String,
     pub value: String,
 }
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct MicrosoftGetEventsResponseEvent {
     pub id: String,
     pub subject: String,
     pub start: MicrosoftGetEventsResponseEventTimestamp,
     pub end: MicrosoftGetEventsResponseEventTimestamp,
-    pub singleValueExtendedProperties: Vec<SingleValueExtendedProperties>,
+    #[serde(default)]
+    pub single_value_extended_properties: Vec<SingleValueExtendedProperties>,
     pub attendees: Vec<MicrosoftGetEventsResponseEventAttendee>,
 }
 
+impl MicrosoftGetEventsResponseEvent {
+    fn extended_property(&self, id: &str) -> Option<&str> {
+        self.single_value_extended_properties
+            .iter()
+            .find(|property| property.id.eq_ignore_ascii_case(id))
+            .map(|property| property.value.as_str())
+    }
+
+    pub fn spielerplus_id(&self) -> Option<&str> {
+        self.extended_property(SP_ID_PROPERTY)
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub struct MicrosoftGetEventsResponse {
     value: Vec<MicrosoftGetEventsResponseEvent>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
 }
 
 pub type ProcessedOutlookEventMap = HashMap<String, MicrosoftGetEventsResponseEvent>;
 
-pub fn list_outlook_events(
-    user_principal_name: &str,
-    calendar_id: &str,
-    current_date: &str,
-    access_token: &str,
-    email_address: &str,
-) -> Result<ProcessedOutlookEventMap, Box<dyn Error>> {
-    let response = reqwest::blocking::Client::new()
-        .get(format!(
-            "https://graph.microsoft.com/v1.0/users/{}/calendars/{}/events",
-            user_principal_name, calendar_id,
-        ))
-        .bearer_auth(access_token)
-        .header("Prefer", "outlook.timezone=\"Europe/Zurich\"")
-        .query(
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftItemBody {
+    pub content_type: String,
+    pub content: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftLocation {
+    pub display_name: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct MicrosoftEmailAddress {
+    pub address: String,
+    pub name: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftAttendee {
+    pub email_address: MicrosoftEmailAddress,
+    pub r#type: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftCreateEventRequest {
+    pub transaction_id: String,
+    pub subject: String,
+    pub body: MicrosoftItemBody,
+    pub start: MicrosoftGetEventsResponseEventTimestamp,
+    pub end: MicrosoftGetEventsResponseEventTimestamp,
+    pub location: MicrosoftLocation,
+    pub single_value_extended_properties: Vec<SingleValueExtendedProperties>,
+    pub attendees: Vec<MicrosoftAttendee>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MicrosoftUpdateEventTimeRequest {
+    pub start: MicrosoftGetEventsResponseEventTimestamp,
+    pub end: MicrosoftGetEventsResponseEventTimestamp,
+}
+
+#[derive(serde::Serialize)]
+pub struct MicrosoftUpdateEventSubjectRequest {
+    pub subject: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct MicrosoftUpdateEventBodyRequest {
+    pub body: MicrosoftItemBody,
+}
+
+#[derive(serde::Serialize)]
+pub struct MicrosoftCancelEventRequest {
+    pub comment: String,
+}
+
+pub struct NewOutlookEvent {
+    pub subject: String,
+    pub body: String,
+    pub start_datetime: String,
+    pub end_datetime: String,
+    pub location_name: String,
+    pub email_address: String,
+    pub spielerplus_id: String,
+    pub first_attempt: Option<DateTime<Utc>>,
+}
+
+impl NewOutlookEvent {
+    fn to_request(
+        &self,
+        user_principal_name: &str,
+        calendar_id: &str,
+    ) -> MicrosoftCreateEventRequest {
+        MicrosoftCreateEventRequest {
+            transaction_id: transaction_id(
+                user_principal_name,
+                calendar_id,
+                &self.email_address,
+                &self.spielerplus_id,
+                self.first_attempt,
+            ),
+            subject: self.subject.clone(),
+            body: MicrosoftItemBody {
+                content_type: "text".to_owned(),
+                content: self.body.clone(),
+            },
+            start: MicrosoftGetEventsResponseEventTimestamp::local(&self.start_datetime),
+            end: MicrosoftGetEventsResponseEventTimestamp::local(&self.end_datetime),
+            location: MicrosoftLocation {
+                display_name: self.location_name.clone(),
+            },
+            single_value_extended_properties: vec![
+                SingleValueExtendedProperties {
+                    id: SP_ID_PROPERTY.to_owned(),
+                    value: self.spielerplus_id.clone(),
+                },
+                SingleValueExtendedProperties {
+                    id: SP_USER_EMAIL_PROPERTY.to_owned(),
+                    value: self.email_address.clone(),
+                },
+            ],
+            attendees: vec![MicrosoftAttendee {
+                email_address: MicrosoftEmailAddress {
+                    address: self.email_address.clone(),
+                    name: self.email_address.clone(),
+                },
+                r#type: "required".to_owned(),
+            }],
+        }
+    }
+}
+
+/// A change Autospieler makes to the calendar. Writes are collected during the sync
+/// and sent together, see [`GraphCalendarClient::send_writes`].
+pub enum OutlookEventWrite {
+    Create(NewOutlookEvent),
+    UpdateTime {
+        event_id: String,
+        new_start_time: String,
+        new_end_time: String,
+    },
+    UpdateSubject {
+        event_id: String,
+        subject: String,
+    },
+    UpdateBody {
+        event_id: String,
+        body: String,
+    },
+    Cancel {
+        event_id: String,
+    },
+}
+
+impl OutlookEventWrite {
+    fn method(&self) -> reqwest::Method {
+        match self {
+            OutlookEventWrite::Create(_) | OutlookEventWrite::Cancel { .. } => {
+                reqwest::Method::POST
+            }
+            OutlookEventWrite::UpdateTime { .. }
+            | OutlookEventWrite::UpdateSubject { .. }
+            | OutlookEventWrite::UpdateBody { .. } => reqwest::Method::PATCH,
+        }
+    }
+
+    /// Path relative to the calendar's `events`, as used in `$batch` requests.
+    fn path(&self) -> String {
+        match self {
+            OutlookEventWrite::Create(_) => String::new(),
+            OutlookEventWrite::UpdateTime { event_id, .. }
+            | OutlookEventWrite::UpdateSubject { event_id, .. }
+            | OutlookEventWrite::UpdateBody { event_id, .. } => format!("/{event_id}"),
+            OutlookEventWrite::Cancel { event_id } => format!("/{event_id}/cancel"),
+        }
+    }
+
+    fn body(
+        &self,
+        user_principal_name: &str,
+        calendar_id: &str,
+    ) -> Result<serde_json::Value, GraphError> {
+        Ok(match self {
+            OutlookEventWrite::Create(event) => {
+                serde_json::to_value(event.to_request(user_principal_name, calendar_id))?
+            }
+            OutlookEventWrite::UpdateTime {
+                new_start_time,
+                new_end_time,
+                ..
+            } => serde_json::to_value(MicrosoftUpdateEventTimeRequest {
+                start: MicrosoftGetEventsResponseEventTimestamp::local(new_start_time),
+                end: MicrosoftGetEventsResponseEventTimestamp::local(new_end_time),
+            })?,
+            OutlookEventWrite::UpdateSubject { subject, .. } => {
+                serde_json::to_value(MicrosoftUpdateEventSubjectRequest {
+                    subject: subject.clone(),
+                })?
+            }
+            OutlookEventWrite::UpdateBody { body, .. } => {
+                serde_json::to_value(MicrosoftUpdateEventBodyRequest {
+                    body: MicrosoftItemBody {
+                        content_type: "text".to_owned(),
+                        content: body.clone(),
+                    },
+                })?
+            }
+            OutlookEventWrite::Cancel { .. } => {
+                serde_json::to_value(MicrosoftCancelEventRequest {
+                    comment: "This event no longer exists in Spielerplus.".to_owned(),
+                })?
+            }
+        })
+    }
+
+    fn expected_status(&self) -> u16 {
+        match self {
+            OutlookEventWrite::Create(_) => 201,
+            OutlookEventWrite::UpdateTime { .. }
+            | OutlookEventWrite::UpdateSubject { .. }
+            | OutlookEventWrite::UpdateBody { .. } => 200,
+            OutlookEventWrite::Cancel { .. } => 202,
+        }
+    }
+}
+
+/// Graph accepts at most 20 requests per `$batch`.
+const BATCH_SIZE: usize = 20;
+
+#[derive(serde::Serialize)]
+struct MicrosoftBatchRequestItem {
+    id: String,
+    method: String,
+    url: String,
+    headers: HashMap<String, String>,
+    body: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+struct MicrosoftBatchRequest {
+    requests: Vec<MicrosoftBatchRequestItem>,
+}
+
+#[derive(serde::Deserialize)]
+struct MicrosoftBatchResponseItem {
+    id: String,
+    status: u16,
+    #[serde(default)]
+    body: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct MicrosoftBatchResponse {
+    responses: Vec<MicrosoftBatchResponseItem>,
+}
+
+/// How far into the future the delta window reaches. Once less than
+/// `DELTA_WINDOW_MIN_REMAINING_DAYS` of it are left, a new window is started.
+const DELTA_WINDOW_DAYS: i64 = 400;
+const DELTA_WINDOW_MIN_REMAINING_DAYS: i64 = 365;
+
+/// Locally persisted result of the `calendarView/delta` queries: the delta link to
+/// continue from and the Autospieler events seen so far, keyed by their Outlook id.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+pub struct OutlookDeltaState {
+    user_principal_name: String,
+    calendar_id: String,
+    email_address: String,
+    window_start: Option<NaiveDate>,
+    window_end: Option<NaiveDate>,
+    delta_link: Option<String>,
+    events: HashMap<String, MicrosoftGetEventsResponseEvent>,
+}
+
+impl OutlookDeltaState {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(OutlookDeltaState::default());
+        }
+
+        let text = fs::read_to_string(path)?;
+        match serde_json::from_str(&text) {
+            Ok(state) => Ok(state),
+            Err(err) => {
+                log::warn!("Ignoring unreadable delta state {}: {err}", path.display());
+                Ok(OutlookDeltaState::default())
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn is_usable_for(
+        &self,
+        user_principal_name: &str,
+        calendar_id: &str,
+        email_address: &str,
+        current_date: NaiveDate,
+    ) -> bool {
+        self.delta_link.is_some()
+            && self.user_principal_name == user_principal_name
+            && self.calendar_id == calendar_id
+            && self.email_address == email_address
+            && self.window_start.is_some_and(|start| start <= current_date)
+            && self.window_end.is_some_and(|end| {
+                end - chrono::Duration::days(DELTA_WINDOW_MIN_REMAINING_DAYS) >= current_date
+            })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct MicrosoftDeltaResponse {
+    value: Vec<serde_json::Value>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+    #[serde(rename = "@odata.deltaLink")]
+    delta_link: Option<String>,
+}
+
+struct MicrosoftDeltaResult {
+    items: Vec<serde_json::Value>,
+    delta_link: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftSubscription {
+    pub id: String,
+    pub expiration_date_time: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftCreateSubscriptionRequest {
+    pub change_type: String,
+    pub notification_url: String,
+    pub resource: String,
+    pub expiration_date_time: DateTime<Utc>,
+    pub client_state: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftRenewSubscriptionRequest {
+    pub expiration_date_time: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftGetScheduleRequest {
+    pub schedules: Vec<String>,
+    pub start_time: MicrosoftGetEventsResponseEventTimestamp,
+    pub end_time: MicrosoftGetEventsResponseEventTimestamp,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftScheduleItem {
+    /// `free`, `tentative`, `busy`, `oof`, `workingElsewhere` or `unknown`.
+    pub status: String,
+    /// Only present if the mailbox shares details.
+    pub subject: Option<String>,
+    pub start: MicrosoftGetEventsResponseEventTimestamp,
+    pub end: MicrosoftGetEventsResponseEventTimestamp,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftScheduleError {
+    pub message: String,
+    #[serde(default)]
+    pub response_code: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftScheduleInformation {
+    #[serde(default)]
+    pub schedule_items: Vec<MicrosoftScheduleItem>,
+    pub error: Option<MicrosoftScheduleError>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MicrosoftGetScheduleResponse {
+    pub value: Vec<MicrosoftScheduleInformation>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MicrosoftAutomaticRepliesSetting {
+    /// `disabled`, `alwaysEnabled` or `scheduled`.
+    pub status: String,
+    pub scheduled_start_date_time: Option<MicrosoftGetEventsResponseEventTimestamp>,
+    pub scheduled_end_date_time: Option<MicrosoftGetEventsResponseEventTimestamp>,
+}
+
+/// Subscriptions on Outlook events may live for at most 4230 minutes.
+const SUBSCRIPTION_LIFETIME_MINUTES: i64 = 4200;
+
+/// Access to the events of one calendar in one mailbox. All requests share one
+/// pooled HTTP client and the token of the `token_provider`.
+pub struct GraphCalendarClient {
+    client: Client,
+    base_url: String,
+    token_provider: Box<dyn TokenProvider>,
+    user_principal_name: String,
+    calendar_id: String,
+}
+
+impl GraphCalendarClient {
+    pub fn new(
+        client: Client,
+        token_provider: Box<dyn TokenProvider>,
+        user_principal_name: &str,
+        calendar_id: &str,
+    ) -> Self {
+        GraphCalendarClient {
+            client,
+            base_url: DEFAULT_GRAPH_BASE_URL.to_owned(),
+            token_provider,
+            user_principal_name: user_principal_name.to_owned(),
+            calendar_id: calendar_id.to_owned(),
+        }
+    }
+
+    /// Sends all requests to `base_url` instead of Graph, e.g. to a local stand-in.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    fn events_path(&self) -> String {
+        format!(
+            "/users/{}/calendars/{}/events",
+            self.user_principal_name, self.calendar_id
+        )
+    }
+
+    fn request(
+        &mut self,
+        method: reqwest::Method,
+        url: &str,
+    ) -> Result<RequestBuilder, GraphError> {
+        let access_token = self.token_provider.access_token()?;
+        Ok(self.client.request(method, url).bearer_auth(access_token))
+    }
+
+    pub fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+        email_address: &str,
+    ) -> Result<ProcessedOutlookEventMap, GraphError> {
+        let mut url = reqwest::Url::parse_with_params(
+            &format!("{}{}", self.base_url, self.events_path()),
             &[
-                ("$expand", "singleValueExtendedProperties($filter=id eq 'String {78ba6e36-6d32-4157-83aa-e36c77df0418} Name SP_ID')"),
-                ("$filter", &format!("singleValueExtendedProperties/Any(ep: ep/id eq 'String {{abdd660a-9ce1-4aa3-b4e7-eca89ccfedea}} Name SP_USER_EMAIL' and ep/value eq '{}') and start/dateTime ge '{}'", email_address, current_date)),
-                ("$select", "id, subject, singleValueExtendedProperties, start, end, attendees")
-            ]
+                ("$expand", format!("singleValueExtendedProperties($filter=id eq '{SP_ID_PROPERTY}')")),
+                ("$filter", format!("singleValueExtendedProperties/Any(ep: ep/id eq '{SP_USER_EMAIL_PROPERTY}' and ep/value eq '{}') and start/dateTime ge '{}'", email_address, current_date)),
+                ("$select", EVENT_SELECT.to_owned())
+            ],
         )
-        .send()?;
+        .map_err(|err| GraphError::InvalidResponse(err.to_string()))?
+        .to_string();
+
+        let mut processed_outlook_event_map = HashMap::new();
+        loop {
+            let response = self
+                .request(reqwest::Method::GET, &url)?
+                .header("Prefer", PREFER_TIME_ZONE)
+                .send_with_retry()?;
+
+            let data = expect_status(response, 200)?.json::<MicrosoftGetEventsResponse>()?;
+
+            for event in data.value {
+                if let Some(spielerplus_id) = event.spielerplus_id() {
+                    processed_outlook_event_map.insert(spielerplus_id.to_owned(), event);
+                }
+            }
+
+            match data.next_link {
+                Some(next_link) => url = next_link,
+                None => break,
+            }
+        }
+
+        Ok(processed_outlook_event_map)
+    }
+
+    /// Fetches a single event including its Spielerplus properties. Returns `None` if the
+    /// event doesn't exist (anymore) or wasn't created by Autospieler for `email_address`.
+    pub fn get_event(
+        &mut self,
+        email_address: &str,
+        event_id: &str,
+    ) -> Result<Option<MicrosoftGetEventsResponseEvent>, GraphError> {
+        let url = format!("{}{}/{}", self.base_url, self.events_path(), event_id);
+        let response = self
+            .request(reqwest::Method::GET, &url)?
+            .header("Prefer", PREFER_TIME_ZONE)
+            .query(&[
+                (
+                    "$expand",
+                    format!(
+                        "singleValueExtendedProperties($filter=id eq '{SP_ID_PROPERTY}' or id eq '{SP_USER_EMAIL_PROPERTY}')"
+                    ),
+                ),
+                ("$select", EVENT_SELECT.to_owned()),
+            ])
+            .send_with_retry()?;
+
+        if response.status().as_u16() == 404 {
+            return Ok(None);
+        }
+
+        let mut event = expect_status(response, 200)?.json::<MicrosoftGetEventsResponseEvent>()?;
+        if event.extended_property(SP_USER_EMAIL_PROPERTY) != Some(email_address)
+            || event.spielerplus_id().is_none()
+        {
+            return Ok(None);
+        }
+
+        event
+            .single_value_extended_properties
+            .retain(|property| property.id.eq_ignore_ascii_case(SP_ID_PROPERTY));
 
-    let response_status = response.status().as_u16();
-    if response_status != 200 {
-        let response_text = response.text()?;
+        Ok(Some(event))
+    }
+
+    /// Follows a delta query through all of its pages. Returns the changed items and the
+    /// delta link for the next round, or `None` if Graph no longer knows the sync state.
+    fn fetch_delta(&mut self, url: &str) -> Result<Option<MicrosoftDeltaResult>, GraphError> {
+        let mut url = url.to_owned();
+        let mut items = Vec::new();
+
+        loop {
+            let response = self
+                .request(reqwest::Method::GET, &url)?
+                .header(
+                    "Prefer",
+                    format!("{PREFER_TIME_ZONE}, odata.maxpagesize=100"),
+                )
+                .send_with_retry()?;
+
+            if response.status().as_u16() == 410 {
+                return Ok(None);
+            }
+
+            let data = expect_status(response, 200)?.json::<MicrosoftDeltaResponse>()?;
+            items.extend(data.value);
+
+            match (data.next_link, data.delta_link) {
+                (Some(next_link), _) => url = next_link,
+                (None, Some(delta_link)) => {
+                    return Ok(Some(MicrosoftDeltaResult { items, delta_link }))
+                }
+                (None, None) => {
+                    return Err(GraphError::InvalidResponse(
+                        "calendarView/delta response has neither a next nor a delta link"
+                            .to_owned(),
+                    ))
+                }
+            }
+        }
+    }
 
-        return Err(format!(
-            "/events request failed with status code {response_status}\n {response_text}"
+    /// Starts a new delta window: acquires a fresh delta link and reads the current
+    /// Autospieler events with a regular filtered query.
+    fn start_delta_window(
+        &mut self,
+        email_address: &str,
+        current_date: NaiveDate,
+        state: &mut OutlookDeltaState,
+    ) -> Result<(), GraphError> {
+        let window_end = current_date + chrono::Duration::days(DELTA_WINDOW_DAYS);
+        let url = reqwest::Url::parse_with_params(
+            &format!(
+                "{}/users/{}/calendars/{}/calendarView/delta",
+                self.base_url, self.user_principal_name, self.calendar_id
+            ),
+            &[
+                ("startDateTime", format!("{current_date}T00:00:00Z")),
+                ("endDateTime", format!("{window_end}T00:00:00Z")),
+            ],
         )
-        .into());
+        .map_err(|err| GraphError::InvalidResponse(err.to_string()))?;
+
+        // The initial round lists every event in the window. Only the delta link is of
+        // interest, the Autospieler events are read with the cheaper filtered query below,
+        // so anything that changes in between shows up again in the next delta round.
+        let delta = self.fetch_delta(url.as_str())?.ok_or_else(|| {
+            GraphError::InvalidResponse("initial calendarView/delta request failed".to_owned())
+        })?;
+
+        let events = self.list_events(current_date, email_address)?;
+
+        *state = OutlookDeltaState {
+            user_principal_name: self.user_principal_name.clone(),
+            calendar_id: self.calendar_id.clone(),
+            email_address: email_address.to_owned(),
+            window_start: Some(current_date),
+            window_end: Some(window_end),
+            delta_link: Some(delta.delta_link),
+            events: events
+                .into_values()
+                .map(|event| (event.id.clone(), event))
+                .collect(),
+        };
+
+        Ok(())
+    }
+
+    /// Applies the changes since the stored delta link. Returns `false` if the delta link
+    /// expired and a new window has to be started.
+    fn apply_delta(
+        &mut self,
+        email_address: &str,
+        state: &mut OutlookDeltaState,
+    ) -> Result<bool, GraphError> {
+        let Some(delta_link) = state.delta_link.clone() else {
+            return Ok(false);
+        };
+        let Some(delta) = self.fetch_delta(&delta_link)? else {
+            return Ok(false);
+        };
+
+        for item in delta.items {
+            let event_id = item["id"]
+                .as_str()
+                .ok_or_else(|| {
+                    GraphError::InvalidResponse("calendarView/delta item without id".to_owned())
+                })?
+                .to_owned();
+
+            if item.get("@removed").is_some() {
+                state.events.remove(&event_id);
+                continue;
+            }
+
+            match state.events.get_mut(&event_id) {
+                Some(known_event) => {
+                    // delta items don't carry extended properties, so keep the stored ones
+                    let changed_event: MicrosoftGetEventsResponseEvent =
+                        serde_json::from_value(item)?;
+                    known_event.subject = changed_event.subject;
+                    known_event.start = changed_event.start;
+                    known_event.end = changed_event.end;
+                    known_event.attendees = changed_event.attendees;
+                }
+                None => {
+                    if let Some(event) = self.get_event(email_address, &event_id)? {
+                        state.events.insert(event_id, event);
+                    }
+                }
+            }
+        }
+
+        state.delta_link = Some(delta.delta_link);
+        Ok(true)
+    }
+
+    /// Like [`GraphCalendarClient::list_events`], but only fetches the events that changed
+    /// since the last call by using `calendarView/delta` and the locally stored `state`.
+    pub fn sync_events(
+        &mut self,
+        current_date: NaiveDate,
+        email_address: &str,
+        state: &mut OutlookDeltaState,
+    ) -> Result<ProcessedOutlookEventMap, GraphError> {
+        let is_up_to_date = state.is_usable_for(
+            &self.user_principal_name,
+            &self.calendar_id,
+            email_address,
+            current_date,
+        ) && self.apply_delta(email_address, state)?;
+
+        if !is_up_to_date {
+            log::info!("Starting new calendarView/delta window");
+            self.start_delta_window(email_address, current_date, state)?;
+        }
+
+        let mut processed_outlook_event_map = HashMap::new();
+        for event in state.events.values() {
+            let Some(spielerplus_id) = event.spielerplus_id() else {
+                continue;
+            };
+            let is_upcoming =
+                NaiveDateTime::parse_from_str(&event.start.date_time, "%Y-%m-%dT%H:%M:%S%.f")
+                    .map(|start| start.date() >= current_date)
+                    .unwrap_or(true);
+
+            if is_upcoming {
+                processed_outlook_event_map.insert(spielerplus_id.to_owned(), event.clone());
+            }
+        }
+
+        Ok(processed_outlook_event_map)
+    }
+
+    /// Sends a single write as its own request.
+    pub fn send_write(&mut self, write: &OutlookEventWrite) -> Result<(), GraphError> {
+        let url = format!("{}{}{}", self.base_url, self.events_path(), write.path());
+        let body = write.body(&self.user_principal_name, &self.calendar_id)?;
+        let request = self
+            .request(write.method(), &url)?
+            .header("content-type", "application/json")
+            .body(body.to_string());
+
+        // Creates are deduplicated by their transactionId and updates set absolute values,
+        // but cancelling twice fails, so it's only repeated if Graph didn't process it.
+        let response = match write {
+            OutlookEventWrite::Cancel { .. } => request.send_with_retry_if_throttled()?,
+            _ => request.send_with_retry()?,
+        };
+
+        expect_status(response, write.expected_status())?;
+        Ok(())
+    }
+
+    /// Sends one `$batch` request. Returns the result per write, or `None` for the writes
+    /// that should be repeated individually (throttled, temporary errors or no answer).
+    fn send_batch(
+        &mut self,
+        writes: &[OutlookEventWrite],
+    ) -> Result<Vec<Option<Result<(), GraphError>>>, GraphError> {
+        let mut requests = Vec::with_capacity(writes.len());
+        for (index, write) in writes.iter().enumerate() {
+            requests.push(MicrosoftBatchRequestItem {
+                id: index.to_string(),
+                method: write.method().to_string(),
+                url: format!("{}{}", self.events_path(), write.path()),
+                headers: HashMap::from([(
+                    "Content-Type".to_owned(),
+                    "application/json".to_owned(),
+                )]),
+                body: write.body(&self.user_principal_name, &self.calendar_id)?,
+            });
+        }
+
+        let url = format!("{}/$batch", self.base_url);
+        let response = self
+            .request(reqwest::Method::POST, &url)?
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&MicrosoftBatchRequest { requests })?)
+            .send_with_retry_if_throttled()?;
+
+        let data = expect_status(response, 200)?.json::<MicrosoftBatchResponse>()?;
+        let mut results: Vec<Option<Result<(), GraphError>>> =
+            writes.iter().map(|_| None).collect();
+
+        for item in data.responses {
+            let Some((index, write)) = item
+                .id
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| Some((index, writes.get(index)?)))
+            else {
+                log::warn!("Ignoring $batch response with unknown id {}", item.id);
+                continue;
+            };
+
+            results[index] = match item.status {
+                status if status == write.expected_status() => Some(Ok(())),
+                429 | 502 | 503 | 504 => None,
+                status => Some(Err(GraphError::from_body(status, &item.body.to_string()))),
+            };
+        }
+
+        Ok(results)
+    }
+
+    /// Sends the writes in `$batch` requests of up to 20 writes. Writes that fail in a
+    /// batch because of throttling or temporary errors, or whose batch failed as a whole,
+    /// are sent individually instead. Returns the result of every write in order.
+    pub fn send_writes(&mut self, writes: &[OutlookEventWrite]) -> Vec<Result<(), GraphError>> {
+        let mut results = Vec::with_capacity(writes.len());
+
+        for chunk in writes.chunks(BATCH_SIZE) {
+            let batch_results = if chunk.len() == 1 {
+                vec![None]
+            } else {
+                self.send_batch(chunk).unwrap_or_else(|err| {
+                    log::warn!("$batch failed, sending the writes individually: {err}");
+                    chunk.iter().map(|_| None).collect()
+                })
+            };
+
+            for (write, batch_result) in chunk.iter().zip(batch_results) {
+                results.push(batch_result.unwrap_or_else(|| self.send_write(write)));
+            }
+        }
+
+        results
+    }
+
+    /// The appointments of `email_address` between `start` and `end`, as far as the
+    /// mailbox shares them with the organizer.
+    pub fn get_schedule(
+        &mut self,
+        email_address: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<MicrosoftScheduleItem>, GraphError> {
+        let request = MicrosoftGetScheduleRequest {
+            schedules: vec![email_address.to_owned()],
+            start_time: MicrosoftGetEventsResponseEventTimestamp::local(&format_graph_date_time(
+                start,
+            )),
+            end_time: MicrosoftGetEventsResponseEventTimestamp::local(&format_graph_date_time(end)),
+        };
+
+        let url = format!(
+            "{}/users/{}/calendar/getSchedule",
+            self.base_url, self.user_principal_name
+        );
+        let response = self
+            .request(reqwest::Method::POST, &url)?
+            .header("Prefer", PREFER_TIME_ZONE)
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&request)?)
+            .send_with_retry()?;
+
+        let schedule = expect_status(response, 200)?
+            .json::<MicrosoftGetScheduleResponse>()?
+            .value
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                GraphError::InvalidResponse(format!("no schedule returned for {email_address}"))
+            })?;
+        // errors of single schedules, e.g. unknown mailboxes, come with 200 OK
+        if let Some(error) = schedule.error {
+            return Err(GraphError::Api {
+                status: 200,
+                code: error.response_code,
+                message: error.message,
+            });
+        }
+
+        Ok(schedule.schedule_items)
+    }
+
+    /// The automatic replies settings of the mailbox of `email_address`.
+    pub fn get_automatic_replies(
+        &mut self,
+        email_address: &str,
+    ) -> Result<MicrosoftAutomaticRepliesSetting, GraphError> {
+        let url = format!(
+            "{}/users/{email_address}/mailboxSettings/automaticRepliesSetting",
+            self.base_url
+        );
+        let response = self
+            .request(reqwest::Method::GET, &url)?
+            .send_with_retry()?;
+
+        Ok(expect_status(response, 200)?.json()?)
+    }
+
+    /// Subscribes `notification_url` to updates of the events in the calendar, which
+    /// includes attendees answering the invitation.
+    pub fn create_event_subscription(
+        &mut self,
+        notification_url: &str,
+        client_state: &str,
+    ) -> Result<MicrosoftSubscription, GraphError> {
+        let request = MicrosoftCreateSubscriptionRequest {
+            change_type: "updated".to_owned(),
+            notification_url: notification_url.to_owned(),
+            resource: self.events_path().trim_start_matches('/').to_owned(),
+            expiration_date_time: Utc::now()
+                + chrono::Duration::minutes(SUBSCRIPTION_LIFETIME_MINUTES),
+            client_state: client_state.to_owned(),
+        };
+
+        let url = format!("{}/subscriptions", self.base_url);
+        let response = self
+            .request(reqwest::Method::POST, &url)?
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&request)?)
+            .send_with_retry_if_throttled()?;
+
+        Ok(expect_status(response, 201)?.json()?)
     }
 
-    let data = response.json::<MicrosoftGetEventsResponse>()?;
+    pub fn renew_subscription(
+        &mut self,
+        subscription_id: &str,
+    ) -> Result<MicrosoftSubscription, GraphError> {
+        let request = MicrosoftRenewSubscriptionRequest {
+            expiration_date_time: Utc::now()
+                + chrono::Duration::minutes(SUBSCRIPTION_LIFETIME_MINUTES),
+        };
+
+        let url = format!("{}/subscriptions/{subscription_id}", self.base_url);
+        let response = self
+            .request(reqwest::Method::PATCH, &url)?
+            .header("content-type", "application/json")
+            .body(serde_json::to_string(&request)?)
+            .send_with_retry()?;
 
-    let mut processed_outlook_event_map = HashMap::new();
-    for event in data.value {
-        processed_outlook_event_map
-            .insert(event.singleValueExtendedProperties[0].value.clone(), event);
+        Ok(expect_status(response, 200)?.json()?)
     }
+}
 
-    Ok(processed_outlook_event_map)
+fn parse_graph_date_time(
+    timestamp: &MicrosoftGetEventsResponseEventTimestamp,
+) -> Result<NaiveDateTime, GraphError> {
+    NaiveDateTime::parse_from_str(&timestamp.date_time, "%Y-%m-%dT%H:%M:%S%.f").map_err(|err| {
+        GraphError::InvalidResponse(format!("invalid dateTime '{}': {err}", timestamp.date_time))
+    })
 }
-pub fn update_event_time(
-    user_principal_name: &str,
-    calendar_id: &str,
-    access_token: &str,
-    event_id: &str,
-    new_start_time: &str,
-    new_end_time: &str,
-) -> Result<serde_json::Value, Box<dyn Error>> {
-    let response = reqwest::blocking::Client::new()
-        .patch(format!(
-            "https://graph.microsoft.com/v1.0/users/{}/calendars/{}/events/{}",
-            user_principal_name, calendar_id, event_id
+
+/// Reads a time Graph didn't return in [`TIME_ZONE`] despite `Prefer`, like those in
+/// mailbox settings, which come in UTC.
+fn parse_graph_date_time_in_any_zone(
+    timestamp: &MicrosoftGetEventsResponseEventTimestamp,
+) -> Result<NaiveDateTime, GraphError> {
+    let date_time = parse_graph_date_time(timestamp)?;
+    let zone: chrono_tz::Tz = timestamp.time_zone.parse().map_err(|_| {
+        GraphError::InvalidResponse(format!("unsupported time zone '{}'", timestamp.time_zone))
+    })?;
+    if zone == LOCAL_TIME_ZONE {
+        return Ok(date_time);
+    }
+    zone_to_local(date_time, zone).ok_or_else(|| {
+        GraphError::InvalidResponse(format!(
+            "{} doesn't exist in time zone {zone}",
+            timestamp.date_time
         ))
-        .header("content-type", "application/json")
-        .body(
-            serde_json::json!({
+    })
+}
 
-                "start": {
-                    "dateTime": new_start_time,
-                    "timeZone": "Europe/Zurich"
-                },
-                "end": {
-                    "dateTime": new_end_time,
-                    "timeZone": "Europe/Zurich"
-                },
-            })
-            .to_string(),
-        )
-        .bearer_auth(access_token)
-        .send()?;
+fn format_graph_date_time(date_time: NaiveDateTime) -> String {
+    date_time.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
 
-    if response.status() != reqwest::StatusCode::from_u16(200).unwrap() {
-        return Err(format!("request failed with status code {}", response.status()).into());
+/// Maps the response of the (only) attendee to an attendance, `None` if they haven't answered yet.
+fn attendance_from_outlook_event(
+    event: &MicrosoftGetEventsResponseEvent,
+) -> Result<Option<Attendance>, GraphError> {
+    let outlook_event_attendence = event
+        .attendees
+        .last()
+        .ok_or_else(|| GraphError::InvalidResponse(format!("event {} has no attendees", event.id)))?
+        .status
+        .response
+        .as_str();
+
+    Ok(match outlook_event_attendence {
+        "none" => None,
+        "accepted" => Some(Attendance::Accepted),
+        "declined" => Some(Attendance::Declined),
+        _ => Some(Attendance::Unsure),
+    })
+}
+
+/// When the (only) attendee responded, `None` if they haven't answered yet.
+fn response_time_from_outlook_event(
+    event: &MicrosoftGetEventsResponseEvent,
+) -> Option<DateTime<Utc>> {
+    let status = &event.attendees.last()?.status;
+    if status.response == "none" {
+        // Graph reports `0001-01-01T00:00:00Z` for missing responses
+        return None;
     }
+    DateTime::parse_from_rfc3339(&status.time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
 
-    Ok(response.json()?)
+fn to_calendar_event(event: &MicrosoftGetEventsResponseEvent) -> Result<CalendarEvent, GraphError> {
+    Ok(CalendarEvent {
+        id: event.id.clone(),
+        spielerplus_id: event
+            .spielerplus_id()
+            .ok_or_else(|| {
+                GraphError::InvalidResponse(format!("event {} has no Spielerplus id", event.id))
+            })?
+            .to_owned(),
+        subject: event.subject.clone(),
+        start: parse_graph_date_time(&event.start)?,
+        end: parse_graph_date_time(&event.end)?,
+        attendance: attendance_from_outlook_event(event)?,
+        response_time: response_time_from_outlook_event(event),
+    })
 }
 
-pub fn cancel_event(
-    user_principal_name: &str,
-    calendar_id: &str,
-    access_token: &str,
-    event_id: &str,
-) -> Result<(), Box<dyn Error>> {
-    let response = reqwest::blocking::Client::new()
-        .post(format!(
-            "https://graph.microsoft.com/v1.0/users/{}/calendars/{}/events/{}/cancel",
-            user_principal_name, calendar_id, event_id
+/// [`CalendarBackend`] for an Outlook calendar, invitations go to `attendee_email`.
+/// Keeps the delta state in `delta_state_path` and, if requested, a subscription for
+/// change notifications.
+pub struct OutlookBackend {
+    graph: GraphCalendarClient,
+    attendee_email: String,
+    delta_state: OutlookDeltaState,
+    delta_state_path: PathBuf,
+    subscription: Option<MicrosoftSubscription>,
+}
+
+impl OutlookBackend {
+    pub fn new(
+        graph: GraphCalendarClient,
+        attendee_email: &str,
+        delta_state_path: PathBuf,
+    ) -> io::Result<Self> {
+        Ok(OutlookBackend {
+            graph,
+            attendee_email: attendee_email.to_owned(),
+            delta_state: OutlookDeltaState::load(&delta_state_path)?,
+            delta_state_path,
+            subscription: None,
+        })
+    }
+
+    fn create_write(&self, event: &NewCalendarEvent) -> OutlookEventWrite {
+        OutlookEventWrite::Create(NewOutlookEvent {
+            subject: event.subject.clone(),
+            body: event.body.clone(),
+            start_datetime: format_graph_date_time(event.start),
+            end_datetime: format_graph_date_time(event.end),
+            location_name: event.location.clone(),
+            email_address: self.attendee_email.clone(),
+            spielerplus_id: event.spielerplus_id.clone(),
+            first_attempt: event.first_attempt,
+        })
+    }
+
+    fn to_write(&self, change: &CalendarChange) -> OutlookEventWrite {
+        match change {
+            CalendarChange::Create(event) => self.create_write(event),
+            CalendarChange::UpdateTime {
+                event_id,
+                start,
+                end,
+            } => OutlookEventWrite::UpdateTime {
+                event_id: event_id.clone(),
+                new_start_time: format_graph_date_time(*start),
+                new_end_time: format_graph_date_time(*end),
+            },
+            CalendarChange::Cancel { event_id } => OutlookEventWrite::Cancel {
+                event_id: event_id.clone(),
+            },
+            CalendarChange::ShowSpielerplusAttendance {
+                event_id, subject, ..
+            } => OutlookEventWrite::UpdateSubject {
+                event_id: event_id.clone(),
+                subject: subject.clone(),
+            },
+            CalendarChange::UpdateBody { event_id, body } => OutlookEventWrite::UpdateBody {
+                event_id: event_id.clone(),
+                body: body.clone(),
+            },
+        }
+    }
+}
+
+impl CalendarBackend for OutlookBackend {
+    fn list_events(
+        &mut self,
+        current_date: NaiveDate,
+    ) -> Result<CalendarEventMap, AutospielerError> {
+        let events =
+            self.graph
+                .sync_events(current_date, &self.attendee_email, &mut self.delta_state)?;
+        self.delta_state.save(&self.delta_state_path)?;
+
+        let mut calendar_events = HashMap::new();
+        for (spielerplus_id, event) in events {
+            calendar_events.insert(spielerplus_id, to_calendar_event(&event)?);
+        }
+        Ok(calendar_events)
+    }
+
+    fn get_event(&mut self, event_id: &str) -> Result<Option<CalendarEvent>, AutospielerError> {
+        match self.graph.get_event(&self.attendee_email, event_id)? {
+            Some(event) => Ok(Some(to_calendar_event(&event)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn create_event(&mut self, event: &NewCalendarEvent) -> Result<(), AutospielerError> {
+        let write = self.create_write(event);
+        Ok(self.graph.send_write(&write)?)
+    }
+
+    fn update_event_time(
+        &mut self,
+        event_id: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<(), AutospielerError> {
+        Ok(self.graph.send_write(&OutlookEventWrite::UpdateTime {
+            event_id: event_id.to_owned(),
+            new_start_time: format_graph_date_time(start),
+            new_end_time: format_graph_date_time(end),
+        })?)
+    }
+
+    fn cancel_event(&mut self, event_id: &str) -> Result<(), AutospielerError> {
+        Ok(self.graph.send_write(&OutlookEventWrite::Cancel {
+            event_id: event_id.to_owned(),
+        })?)
+    }
+
+    fn show_spielerplus_attendance(
+        &mut self,
+        event_id: &str,
+        subject: &str,
+        _attendance: Option<Attendance>,
+    ) -> Result<(), AutospielerError> {
+        Ok(self.graph.send_write(&OutlookEventWrite::UpdateSubject {
+            event_id: event_id.to_owned(),
+            subject: subject.to_owned(),
+        })?)
+    }
+
+    fn update_event_body(&mut self, event_id: &str, body: &str) -> Result<(), AutospielerError> {
+        Ok(self.graph.send_write(&OutlookEventWrite::UpdateBody {
+            event_id: event_id.to_owned(),
+            body: body.to_owned(),
+        })?)
+    }
+
+    fn busy_times(
+        &mut self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<BusyTime>, AutospielerError> {
+        let items = self.graph.get_schedule(&self.attendee_email, start, end)?;
+
+        let mut busy_times = Vec::new();
+        for item in items {
+            if item.status == "busy" || item.status == "oof" {
+                busy_times.push(BusyTime {
+                    start: parse_graph_date_time(&item.start)?,
+                    end: parse_graph_date_time(&item.end)?,
+                    subject: item.subject,
+                });
+            }
+        }
+        Ok(busy_times)
+    }
+
+    fn out_of_office(&mut self) -> Result<Option<OutOfOffice>, AutospielerError> {
+        let setting = self.graph.get_automatic_replies(&self.attendee_email)?;
+        let parse = |timestamp: &Option<MicrosoftGetEventsResponseEventTimestamp>| {
+            timestamp
+                .as_ref()
+                .map(parse_graph_date_time_in_any_zone)
+                .transpose()
+        };
+
+        match setting.status.as_str() {
+            "alwaysEnabled" => Ok(Some(OutOfOffice {
+                start: None,
+                end: None,
+            })),
+            "scheduled" => Ok(Some(OutOfOffice {
+                start: parse(&setting.scheduled_start_date_time)?,
+                end: parse(&setting.scheduled_end_date_time)?,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    fn apply_changes(&mut self, changes: &[CalendarChange]) -> Vec<Result<(), AutospielerError>> {
+        let writes: Vec<_> = changes.iter().map(|change| self.to_write(change)).collect();
+        self.graph
+            .send_writes(&writes)
+            .into_iter()
+            .map(|result| result.map_err(AutospielerError::from))
+            .collect()
+    }
+
+    fn ensure_change_notifications(
+        &mut self,
+        notification_url: &str,
+        client_state: &str,
+    ) -> Result<(), AutospielerError> {
+        let renew_after = Utc::now() + chrono::Duration::hours(12);
+
+        self.subscription = match self.subscription.take() {
+            Some(subscription) if subscription.expiration_date_time > renew_after => {
+                Some(subscription)
+            }
+            Some(subscription) => match self.graph.renew_subscription(&subscription.id) {
+                Ok(subscription) => Some(subscription),
+                Err(err) => {
+                    log::warn!("Could not renew subscription {}: {err}", subscription.id);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if self.subscription.is_none() {
+            self.subscription = Some(
+                self.graph
+                    .create_event_subscription(notification_url, client_state)?,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::{Reply, StubServer};
+    use serde_json::json;
+
+    const USER_PRINCIPAL_NAME: &str = "team@example.org";
+    const ATTENDEE_EMAIL: &str = "me@example.org";
+
+    struct StaticToken;
+
+    impl TokenProvider for StaticToken {
+        fn access_token(&mut self) -> Result<String, GraphError> {
+            Ok("token".to_owned())
+        }
+    }
+
+    fn graph_client(server: &StubServer) -> GraphCalendarClient {
+        GraphCalendarClient::new(
+            Client::new(),
+            Box::new(StaticToken),
+            USER_PRINCIPAL_NAME,
+            "cal",
+        )
+        .with_base_url(server.base_url())
+    }
+
+    fn date(input: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(input, "%Y-%m-%d").unwrap()
+    }
+
+    /// An event like it comes in a `calendarView/delta` round, without extended properties.
+    fn delta_item(id: &str, start: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "subject": "Training",
+            "start": {"dateTime": format!("{start}:00.0000000"), "timeZone": TIME_ZONE},
+            "end": {"dateTime": format!("{start}:00.0000000"), "timeZone": TIME_ZONE},
+            "attendees": [
+                {"type": "required", "status": {"response": "none", "time": "0001-01-01T00:00:00Z"}}
+            ]
+        })
+    }
+
+    fn outlook_event(id: &str, spielerplus_id: &str, start: &str) -> serde_json::Value {
+        let mut event = delta_item(id, start);
+        event["singleValueExtendedProperties"] = json!([
+            {"id": SP_ID_PROPERTY, "value": spielerplus_id},
+            {"id": SP_USER_EMAIL_PROPERTY, "value": ATTENDEE_EMAIL}
+        ]);
+        event
+    }
+
+    /// Answers the requests that start a new delta window: two pages of
+    /// `calendarView/delta` and the filtered event list with `events`.
+    fn delta_window_reply(url: &str, events: &serde_json::Value) -> Option<Reply> {
+        if url.contains("/calendarView/delta?startDateTime=") {
+            Some(Reply::json(
+                200,
+                &json!({
+                    "value": [delta_item("foreign", "2024-05-03T10:00")],
+                    "@odata.nextLink": "{base}/delta-page-2"
+                })
+                .to_string(),
+            ))
+        } else if url == "/delta-page-2" {
+            Some(Reply::json(
+                200,
+                r#"{"value": [], "@odata.deltaLink": "{base}/delta?token=1"}"#,
+            ))
+        } else if url.contains("/calendars/cal/events?") {
+            Some(Reply::json(200, &json!({ "value": events }).to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn delta_state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "autospieler-test-{}-{name}.json",
+            std::process::id()
         ))
-        .header("content-type", "application/json")
-        .body(
-            serde_json::json!({
-                "comment": "This event no longer exists in Spielerplus."
+    }
+
+    fn at(input: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(input)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn uses_the_same_transaction_id_for_retries_of_a_create() {
+        let first_attempt = Some(at("2024-05-01T10:00:00Z"));
+        let id = transaction_id(
+            "team@example.org",
+            "cal",
+            "me@example.org",
+            "42",
+            first_attempt,
+        );
+
+        assert_eq!(
+            id,
+            transaction_id(
+                "team@example.org",
+                "cal",
+                "me@example.org",
+                "42",
+                first_attempt
+            )
+        );
+        assert_ne!(
+            id,
+            transaction_id(
+                "team@example.org",
+                "cal",
+                "you@example.org",
+                "42",
+                first_attempt
+            )
+        );
+        assert_ne!(
+            id,
+            transaction_id(
+                "team@example.org",
+                "cal",
+                "me@example.org",
+                "43",
+                first_attempt
+            )
+        );
+    }
+
+    #[test]
+    fn uses_a_new_transaction_id_when_an_event_is_created_again() {
+        let id = |first_attempt| {
+            transaction_id(
+                "team@example.org",
+                "cal",
+                "me@example.org",
+                "42",
+                first_attempt,
+            )
+        };
+
+        assert_ne!(
+            id(Some(at("2024-05-01T10:00:00Z"))),
+            id(Some(at("2024-05-08T10:00:00Z")))
+        );
+        assert_ne!(id(Some(at("2024-05-01T10:00:00Z"))), id(None));
+    }
+
+    #[test]
+    fn starts_a_delta_window_and_continues_from_the_saved_delta_link() {
+        let listed = json!([
+            outlook_event("A", "42", "2024-05-02T19:00"),
+            outlook_event("B", "43", "2024-05-09T19:00")
+        ]);
+        let changes = json!({
+            "value": [
+                delta_item("A", "2024-05-02T20:00"),
+                {"id": "B", "@removed": {"reason": "deleted"}},
+                {"id": "C"},
+                {"id": "D"}
+            ],
+            "@odata.deltaLink": "{base}/delta?token=2"
+        })
+        .to_string();
+        let created = outlook_event("C", "44", "2024-05-16T19:00").to_string();
+        let mut foreign = outlook_event("D", "45", "2024-05-23T19:00");
+        foreign["singleValueExtendedProperties"][1]["value"] = json!("you@example.org");
+        let foreign = foreign.to_string();
+
+        let server = StubServer::start(move |request| {
+            if let Some(reply) = delta_window_reply(&request.url, &listed) {
+                reply
+            } else if request.url == "/delta?token=1" {
+                Reply::json(200, &changes)
+            } else if request.url.contains("/events/C?") {
+                Reply::json(200, &created)
+            } else if request.url.contains("/events/D?") {
+                Reply::json(200, &foreign)
+            } else {
+                Reply::json(404, "{}")
+            }
+        });
+        let mut graph = graph_client(&server);
+        let path = delta_state_path("continue");
+
+        let mut state = OutlookDeltaState::load(&path).unwrap();
+        let events = graph
+            .sync_events(date("2024-05-01"), ATTENDEE_EMAIL, &mut state)
+            .unwrap();
+        state.save(&path).unwrap();
+
+        let mut spielerplus_ids: Vec<_> = events.keys().cloned().collect();
+        spielerplus_ids.sort();
+        assert_eq!(spielerplus_ids, ["42", "43"]);
+
+        let mut state = OutlookDeltaState::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(state.is_usable_for(
+            USER_PRINCIPAL_NAME,
+            "cal",
+            ATTENDEE_EMAIL,
+            date("2024-05-01")
+        ));
+        assert!(!state.is_usable_for(
+            USER_PRINCIPAL_NAME,
+            "cal",
+            "you@example.org",
+            date("2024-05-01")
+        ));
+
+        let events = graph
+            .sync_events(date("2024-05-01"), ATTENDEE_EMAIL, &mut state)
+            .unwrap();
+
+        let mut spielerplus_ids: Vec<_> = events.keys().cloned().collect();
+        spielerplus_ids.sort();
+        assert_eq!(spielerplus_ids, ["42", "44"]);
+        assert_eq!(events["42"].start.date_time, "2024-05-02T20:00:00.0000000");
+        // the stored extended properties survive the delta item without them
+        assert_eq!(events["42"].spielerplus_id(), Some("42"));
+        assert!(state
+            .delta_link
+            .as_ref()
+            .unwrap()
+            .ends_with("/delta?token=2"));
+
+        let requests = server.requests();
+        assert!(requests.iter().all(|request| request.method == "GET"));
+        let urls: Vec<_> = requests.into_iter().map(|request| request.url).collect();
+        assert_eq!(urls.len(), 6);
+        assert_eq!(urls[3], "/delta?token=1");
+        assert_eq!(
+            urls.iter()
+                .filter(|url| url.contains("calendarView/delta?startDateTime="))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn starts_a_new_delta_window_when_the_delta_link_expired() {
+        let listed = json!([outlook_event("A", "42", "2024-05-02T19:00")]);
+        let server = StubServer::start(move |request| {
+            if let Some(reply) = delta_window_reply(&request.url, &listed) {
+                reply
+            } else if request.url == "/delta?token=expired" {
+                Reply::json(
+                    410,
+                    r#"{"error": {"code": "SyncStateNotFound", "message": "expired"}}"#,
+                )
+            } else {
+                Reply::json(404, "{}")
+            }
+        });
+        let mut graph = graph_client(&server);
+        let mut state = OutlookDeltaState {
+            user_principal_name: USER_PRINCIPAL_NAME.to_owned(),
+            calendar_id: "cal".to_owned(),
+            email_address: ATTENDEE_EMAIL.to_owned(),
+            window_start: Some(date("2024-04-01")),
+            window_end: Some(date("2025-05-06")),
+            delta_link: Some(format!("{}/delta?token=expired", server.base_url())),
+            events: HashMap::new(),
+        };
+
+        let events = graph
+            .sync_events(date("2024-05-01"), ATTENDEE_EMAIL, &mut state)
+            .unwrap();
+
+        assert_eq!(events.keys().collect::<Vec<_>>(), ["42"]);
+        assert_eq!(state.window_start, Some(date("2024-05-01")));
+        assert!(state
+            .delta_link
+            .as_ref()
+            .unwrap()
+            .ends_with("/delta?token=1"));
+        assert_eq!(server.requests()[0].url, "/delta?token=expired");
+    }
+
+    #[test]
+    fn rolls_the_delta_window_over_before_it_runs_out() {
+        let listed = json!([
+            outlook_event("A", "42", "2024-04-30T19:00"),
+            outlook_event("B", "43", "2024-05-02T19:00")
+        ]);
+        let server = StubServer::start(move |request| {
+            delta_window_reply(&request.url, &listed).unwrap_or_else(|| Reply::json(404, "{}"))
+        });
+        let mut graph = graph_client(&server);
+        let mut state = OutlookDeltaState {
+            user_principal_name: USER_PRINCIPAL_NAME.to_owned(),
+            calendar_id: "cal".to_owned(),
+            email_address: ATTENDEE_EMAIL.to_owned(),
+            window_start: Some(date("2024-01-01")),
+            window_end: Some(date("2025-02-04")),
+            delta_link: Some(format!("{}/delta?token=old", server.base_url())),
+            events: HashMap::new(),
+        };
+
+        let events = graph
+            .sync_events(date("2024-05-01"), ATTENDEE_EMAIL, &mut state)
+            .unwrap();
+
+        // events before the current date are left out
+        assert_eq!(events.keys().collect::<Vec<_>>(), ["43"]);
+        assert_eq!(state.window_start, Some(date("2024-05-01")));
+        assert_eq!(state.window_end, Some(date("2025-06-05")));
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.url != "/delta?token=old"));
+    }
+
+    fn subject_writes(count: usize) -> Vec<OutlookEventWrite> {
+        (0..count)
+            .map(|index| OutlookEventWrite::UpdateSubject {
+                event_id: format!("event-{index}"),
+                subject: "[Zugesagt] Training".to_owned(),
             })
-            .to_string(),
-        )
-        .bearer_auth(access_token)
-        .send()?;
+            .collect()
+    }
+
+    /// The ids of the requests in a `$batch` body.
+    fn batch_ids(body: &str) -> Vec<String> {
+        let batch: serde_json::Value = serde_json::from_str(body).unwrap();
+        batch["requests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|request| request["id"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sends_writes_in_batches_of_twenty() {
+        let server = StubServer::start(|request| {
+            // Graph doesn't keep the order of the requests in its responses
+            let responses: Vec<_> = batch_ids(&request.body)
+                .into_iter()
+                .rev()
+                .map(|id| json!({"id": id, "status": 200, "body": {}}))
+                .collect();
+            Reply::json(200, &json!({ "responses": responses }).to_string())
+        });
+        let mut graph = graph_client(&server);
+
+        let results = graph.send_writes(&subject_writes(45));
+
+        assert_eq!(results.len(), 45);
+        assert!(results.iter().all(Result::is_ok));
+        let requests = server.requests();
+        assert!(requests.iter().all(|request| request.url == "/$batch"));
+        let batch_sizes: Vec<_> = requests
+            .iter()
+            .map(|request| batch_ids(&request.body).len())
+            .collect();
+        assert_eq!(batch_sizes, [20, 20, 5]);
+        let batch: serde_json::Value = serde_json::from_str(&requests[2].body).unwrap();
+        assert_eq!(batch["requests"][4]["id"], "4");
+        assert_eq!(batch["requests"][4]["method"], "PATCH");
+        assert_eq!(
+            batch["requests"][4]["url"],
+            "/users/team@example.org/calendars/cal/events/event-44"
+        );
+    }
+
+    #[test]
+    fn maps_batch_responses_back_to_the_writes() {
+        let server = StubServer::start(|request| {
+            if request.url != "/$batch" {
+                return Reply::json(200, "{}");
+            }
+            Reply::json(
+                200,
+                r#"{"responses": [
+                    {"id": "3", "status": 200, "body": {}},
+                    {"id": "7", "status": 200, "body": {}},
+                    {"id": "0", "status": 404, "body": {"error": {"code": "ErrorItemNotFound", "message": "not found"}}},
+                    {"id": "2", "status": 500, "body": {"error": {"code": "generalException", "message": "failed"}}},
+                    {"id": "1", "status": 429, "body": {"error": {"code": "ApplicationThrottled", "message": "slow down"}}}
+                ]}"#,
+            )
+        });
+        let mut graph = graph_client(&server);
+
+        let results = graph.send_writes(&subject_writes(5));
+
+        assert!(matches!(
+            &results[0],
+            Err(GraphError::Api { status: 404, code, .. }) if code == "ErrorItemNotFound"
+        ));
+        assert!(results[1].is_ok());
+        assert!(matches!(
+            &results[2],
+            Err(GraphError::Api { status: 500, code, .. }) if code == "generalException"
+        ));
+        assert!(results[3].is_ok());
+        assert!(results[4].is_ok());
 
-    if response.status() != reqwest::StatusCode::from_u16(202).unwrap() {
-        return Err(format!("request failed with status code {}", response.status()).into());
+        // the throttled write and the one without a response are repeated on their own
+        let urls: Vec<_> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.url)
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "/$batch",
+                "/users/team@example.org/calendars/cal/events/event-1",
+                "/users/team@example.org/calendars/cal/events/event-4",
+            ]
+        );
     }
 
-    Ok(())
+    #[test]
+    fn sends_the_writes_individually_if_the_batch_fails() {
+        let server = StubServer::start(|request| match request.url.as_str() {
+            "/$batch" => Reply::json(
+                500,
+                r#"{"error": {"code": "generalException", "message": "failed"}}"#,
+            ),
+            _ => Reply::json(200, "{}"),
+        });
+        let mut graph = graph_client(&server);
+
+        let results = graph.send_writes(&subject_writes(3));
+
+        assert!(results.iter().all(Result::is_ok));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[1..].iter().all(|request| request.method == "PATCH"
+            && request.body == r#"{"subject":"[Zugesagt] Training"}"#));
+    }
+
+    #[test]
+    fn sends_a_single_write_without_a_batch() {
+        let server = StubServer::start(|_| Reply::json(202, ""));
+        let mut graph = graph_client(&server);
+
+        let results = graph.send_writes(&[OutlookEventWrite::Cancel {
+            event_id: "event-0".to_owned(),
+        }]);
+
+        assert!(results[0].is_ok());
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(
+            requests[0].url,
+            "/users/team@example.org/calendars/cal/events/event-0/cancel"
+        );
+    }
+
+    #[test]
+    fn reads_graph_errors_from_the_body() {
+        let err = GraphError::from_body(
+            403,
+            r#"{"error": {"code": "ErrorAccessDenied", "message": "Access is denied."}}"#,
+        );
+        assert!(matches!(
+            &err,
+            GraphError::Api { status: 403, message, .. } if message == "Access is denied."
+        ));
+        assert_eq!(err.code(), Some("ErrorAccessDenied"));
+
+        let err = GraphError::from_body(502, "Bad Gateway");
+        assert!(matches!(
+            &err,
+            GraphError::Api { status: 502, message, .. } if message == "Bad Gateway"
+        ));
+        assert_eq!(err.code(), Some(""));
+    }
+
+    #[test]
+    fn classifies_graph_responses() {
+        let server = StubServer::start(|request| match request.url.split('?').next().unwrap() {
+            "/users/team@example.org/calendars/cal/events/deleted" => Reply::json(
+                404,
+                r#"{"error": {"code": "ErrorItemNotFound", "message": "not found"}}"#,
+            ),
+            "/users/team@example.org/calendars/cal/events/forbidden" => Reply::json(
+                403,
+                r#"{"error": {"code": "ErrorAccessDenied", "message": "Access is denied."}}"#,
+            ),
+            "/users/team@example.org/calendars/cal/events/garbled" => {
+                Reply::json(200, r#"{"id": "garbled""#)
+            }
+            "/users/team@example.org/calendars/cal/events/throttled" => Reply::json(
+                429,
+                r#"{"error": {"code": "ApplicationThrottled", "message": "slow down"}}"#,
+            )
+            // longer than the retry policy waits
+            .with_header("Retry-After", "3600"),
+            _ => Reply::json(
+                200,
+                &outlook_event("foreign", "42", "2024-05-02T19:00").to_string(),
+            ),
+        });
+        let mut graph = graph_client(&server);
+
+        assert!(graph
+            .get_event(ATTENDEE_EMAIL, "deleted")
+            .unwrap()
+            .is_none());
+        assert!(graph
+            .get_event("you@example.org", "foreign")
+            .unwrap()
+            .is_none());
+        assert!(graph
+            .get_event(ATTENDEE_EMAIL, "foreign")
+            .unwrap()
+            .is_some());
+
+        let err = graph.get_event(ATTENDEE_EMAIL, "forbidden").err().unwrap();
+        assert!(matches!(err, GraphError::Api { status: 403, .. }));
+        assert_eq!(
+            AutospielerError::from(err).exit_code(),
+            AutospielerError::Calendar(String::new()).exit_code()
+        );
+
+        let err = graph.get_event(ATTENDEE_EMAIL, "garbled").err().unwrap();
+        assert!(matches!(err, GraphError::InvalidResponse(_)));
+
+        let err = graph.get_event(ATTENDEE_EMAIL, "throttled").err().unwrap();
+        assert_eq!(err.code(), Some("ApplicationThrottled"));
+        assert!(matches!(err, GraphError::Api { status: 429, .. }));
+        assert_eq!(
+            server
+                .requests()
+                .iter()
+                .filter(|request| request.url.contains("/throttled?"))
+                .count(),
+            1
+        );
+    }
+
+    fn token_cache(server: &StubServer) -> MicrosoftTokenCache {
+        let mut cache = MicrosoftTokenCache::new(Client::new(), "client", "secret", "tenant");
+        cache.login_base_url = server.base_url().to_owned();
+        cache
+    }
+
+    #[test]
+    fn requests_a_new_token_shortly_before_it_expires() {
+        let server = StubServer::start(|_| {
+            Reply::json(200, r#"{"access_token": "token", "expires_in": 3600}"#)
+        });
+        let mut cache = token_cache(&server);
+
+        assert_eq!(cache.access_token().unwrap(), "token");
+        assert_eq!(cache.access_token().unwrap(), "token");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url, "/tenant/oauth2/v2.0/token");
+        assert!(requests[0].body.contains("grant_type=client_credentials"));
+
+        cache.token.as_mut().unwrap().expires_at = Instant::now() + Duration::from_secs(6 * 60);
+        cache.access_token().unwrap();
+        assert_eq!(server.requests().len(), 1);
+
+        cache.token.as_mut().unwrap().expires_at = Instant::now() + Duration::from_secs(4 * 60);
+        cache.access_token().unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert!(
+            cache.token.as_ref().unwrap().expires_at
+                > Instant::now() + Duration::from_secs(50 * 60)
+        );
+    }
+
+    #[test]
+    fn reports_failed_token_requests_as_authentication_errors() {
+        let server = StubServer::start(|_| {
+            Reply::json(
+                401,
+                r#"{"error": "invalid_client", "error_description": "AADSTS7000215: Invalid client secret provided."}"#,
+            )
+        });
+        let mut cache = token_cache(&server);
+
+        let err = cache.access_token().err().unwrap();
+
+        assert!(matches!(
+            &err,
+            GraphError::Authentication { status: 401, code, .. } if code == "invalid_client"
+        ));
+        assert_eq!(AutospielerError::from(err).exit_code(), 3);
+        assert!(cache.token.is_none());
+    }
 }
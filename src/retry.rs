@@ -0,0 +1,263 @@
+use crate::{error::AutospielerError, read_parsed_env};
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::RETRY_AFTER,
+    StatusCode,
+};
+use std::{sync::OnceLock, thread, time::Duration};
+
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `1` disables retrying.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    /// Upper bound for the backoff. A `Retry-After` longer than this isn't waited for.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let default = RetryPolicy::default();
+        let max_attempts = read_parsed_env("RETRY_MAX_ATTEMPTS", default.max_attempts)?;
+        if max_attempts == 0 {
+            return Err(AutospielerError::config(
+                "RETRY_MAX_ATTEMPTS must be at least 1",
+            ));
+        }
+
+        Ok(RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(read_parsed_env(
+                "RETRY_INITIAL_DELAY_MS",
+                default.initial_delay.as_millis() as u64,
+            )?),
+            max_delay: Duration::from_secs(read_parsed_env(
+                "RETRY_MAX_DELAY_SECONDS",
+                default.max_delay.as_secs(),
+            )?),
+        })
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay)
+    }
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Sets the policy used by [`SendWithRetry`]. Without it, the default policy is used.
+pub fn set_policy(policy: RetryPolicy) {
+    if POLICY.set(policy).is_err() {
+        log::warn!("Retry policy was already set, ignoring the new one");
+    }
+}
+
+fn policy() -> &'static RetryPolicy {
+    POLICY.get_or_init(RetryPolicy::default)
+}
+
+/// Parses a `Retry-After` value, which is either a number of seconds or an HTTP date.
+/// Dates in the past mean retrying right away.
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Non-idempotent requests are only repeated if the server is known to have rejected
+/// them without doing anything, which is what 429 means.
+fn should_retry(status: StatusCode, idempotent: bool) -> bool {
+    if idempotent {
+        is_transient_status(status)
+    } else {
+        status == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+fn send(request: RequestBuilder, idempotent: bool) -> reqwest::Result<Response> {
+    let policy = policy();
+    let mut attempt = 1;
+
+    loop {
+        let Some(current_request) = request
+            .try_clone()
+            .filter(|_| attempt < policy.max_attempts)
+        else {
+            return request.send();
+        };
+
+        let delay = match current_request.send() {
+            Ok(response) => {
+                if !should_retry(response.status(), idempotent) {
+                    return Ok(response);
+                }
+
+                match retry_after(&response) {
+                    Some(delay) if delay > policy.max_delay => return Ok(response),
+                    Some(delay) => delay,
+                    None => policy.backoff(attempt),
+                }
+            }
+            Err(err) if idempotent && (err.is_timeout() || err.is_connect()) => {
+                policy.backoff(attempt)
+            }
+            Err(err) => return Err(err),
+        };
+
+        log::warn!(
+            "Attempt {attempt}/{} failed, retrying in {}ms",
+            policy.max_attempts,
+            delay.as_millis()
+        );
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+pub trait SendWithRetry {
+    /// Sends the request, repeating it on timeouts, connection errors, throttling and
+    /// temporary server errors. Only use this for requests that are safe to repeat.
+    fn send_with_retry(self) -> reqwest::Result<Response>;
+
+    /// Sends the request, repeating it only if it was throttled (429), in which case the
+    /// server didn't process it. Use this for requests that must not run twice.
+    fn send_with_retry_if_throttled(self) -> reqwest::Result<Response>;
+}
+
+impl SendWithRetry for RequestBuilder {
+    fn send_with_retry(self) -> reqwest::Result<Response> {
+        send(self, true)
+    }
+
+    fn send_with_retry_if_throttled(self) -> reqwest::Result<Response> {
+        send(self, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_server::{Reply, StubServer};
+    use reqwest::blocking::Client;
+
+    fn now() -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::parse_from_rfc3339("2024-05-02T19:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc)
+    }
+
+    #[test]
+    fn parses_retry_after_in_seconds_and_as_date() {
+        assert_eq!(
+            parse_retry_after(" 120 ", now()),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Thu, 02 May 2024 19:01:30 GMT", now()),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Thu, 02 May 2024 18:00:00 GMT", now()),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("-1", now()), None);
+        assert_eq!(parse_retry_after("soon", now()), None);
+    }
+
+    #[test]
+    fn caps_the_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(3));
+        assert_eq!(policy.backoff(40), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn only_retries_non_idempotent_requests_when_throttled() {
+        for status in [429, 502, 503, 504] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(should_retry(status, true));
+            assert_eq!(
+                should_retry(status, false),
+                status == StatusCode::TOO_MANY_REQUESTS
+            );
+        }
+        for status in [200, 400, 404, 412, 500] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(!should_retry(status, true));
+            assert!(!should_retry(status, false));
+        }
+    }
+
+    /// Sends a request to a server answering `status` with `Retry-After: retry_after`
+    /// and returns how often it was sent.
+    fn attempts(status: u16, retry_after: &str, idempotent: bool) -> usize {
+        let retry_after = retry_after.to_owned();
+        let server = StubServer::start(move |_| {
+            Reply::json(status, "{}").with_header("Retry-After", &retry_after)
+        });
+        let request = Client::new().post(server.base_url());
+        let response = if idempotent {
+            request.send_with_retry()
+        } else {
+            request.send_with_retry_if_throttled()
+        };
+
+        assert_eq!(response.unwrap().status().as_u16(), status);
+        server.requests().len()
+    }
+
+    #[test]
+    fn retries_according_to_the_policy() {
+        let max_attempts = policy().max_attempts as usize;
+
+        assert_eq!(attempts(503, "0", true), max_attempts);
+        assert_eq!(attempts(503, "0", false), 1);
+        assert_eq!(attempts(429, "0", false), max_attempts);
+        assert_eq!(attempts(500, "0", true), 1);
+        // a Retry-After beyond the maximum delay isn't waited for
+        assert_eq!(attempts(429, "3600", true), 1);
+    }
+}
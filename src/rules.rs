@@ -0,0 +1,62 @@
+use crate::{
+    calendar::Attendance,
+    error::AutospielerError,
+    filters::{EventFilter, FilteredEvent},
+    read_optional_env,
+    spielerplus::Team,
+};
+use chrono::NaiveDate;
+use std::fs;
+
+/// Answers events matching `when` with `answer`.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DauerzusageRule {
+    /// Conditions like in an event filter, every event matches if it's left out.
+    #[serde(default)]
+    pub when: EventFilter,
+    pub answer: Attendance,
+}
+
+/// Rules from the JSON file in `DAUERZUSAGE_RULES_FILE`, answering events in Spielerplus
+/// that nobody answered yet, neither in Spielerplus nor in the calendar.
+#[derive(serde::Deserialize, Default)]
+#[serde(transparent)]
+pub struct DauerzusageRules {
+    rules: Vec<DauerzusageRule>,
+}
+
+impl DauerzusageRules {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        let Some(path) = read_optional_env("DAUERZUSAGE_RULES_FILE")? else {
+            return Ok(DauerzusageRules::default());
+        };
+
+        let text = fs::read_to_string(&path).map_err(|e| {
+            AutospielerError::config(format!("Could not read DAUERZUSAGE_RULES_FILE {path}: {e}"))
+        })?;
+        let rules = serde_json::from_str(&text).map_err(|e| {
+            AutospielerError::config(format!(
+                "Could not parse DAUERZUSAGE_RULES_FILE {path}: {e}"
+            ))
+        })?;
+
+        Ok(DauerzusageRules { rules })
+    }
+
+    /// The answer of the first rule matching the event.
+    pub fn answer(
+        &self,
+        profile_id: &str,
+        team: Option<&Team>,
+        event: &FilteredEvent,
+        today: NaiveDate,
+    ) -> Option<Attendance> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.when.is_for(profile_id, team) && rule.when.rejection(event, today).is_none()
+            })
+            .map(|rule| rule.answer)
+    }
+}
@@ -0,0 +1,385 @@
+use crate::{error::AutospielerError, read_optional_env, retry::SendWithRetry};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use reqwest::blocking::Client;
+
+/// Page listing every team profile of the logged in account.
+const SELECT_TEAM_URL: &str = "https://www.spielerplus.de/site/select-team";
+
+/// A team profile of the logged in account, as listed on the "Team auswählen" page.
+#[derive(Clone, Debug)]
+pub struct Team {
+    /// Id of the profile in the team, used in `switch-user?id=` and `DAUERZUSAGE_ID`.
+    pub profile_id: String,
+    pub name: String,
+    /// Role in the team as Spielerplus shows it, e.g. "Spieler" or "Trainer".
+    pub role: Option<String>,
+}
+
+/// Which teams are synced: the profiles in `DAUERZUSAGE_ID`, or all teams of the
+/// account if it isn't set. Both can be narrowed down by team name or profile id.
+pub struct TeamSelection {
+    profile_ids: Option<Vec<String>>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+fn read_list_env(name: &str) -> Result<Vec<String>, AutospielerError> {
+    Ok(read_optional_env(name)?
+        .map(|list| {
+            list.split(',')
+                .map(|entry| entry.trim().to_owned())
+                .filter(|entry| !entry.is_empty())
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+impl TeamSelection {
+    pub fn from_env() -> Result<Self, AutospielerError> {
+        Ok(TeamSelection {
+            profile_ids: read_optional_env("DAUERZUSAGE_ID")?.map(|ids| {
+                ids.split(",")
+                    .map(|id| id.trim().to_owned())
+                    .filter(|id| !id.is_empty())
+                    .collect()
+            }),
+            include: read_list_env("DAUERZUSAGE_TEAMS_INCLUDE")?,
+            exclude: read_list_env("DAUERZUSAGE_TEAMS_EXCLUDE")?,
+        })
+    }
+
+    fn matches(entries: &[String], profile_id: &str, team: Option<&Team>) -> bool {
+        entries.iter().any(|entry| {
+            entry == profile_id || team.is_some_and(|team| entry.eq_ignore_ascii_case(&team.name))
+        })
+    }
+
+    /// The profile ids to sync, given the teams of the account.
+    pub fn profile_ids(&self, teams: &[Team]) -> Result<Vec<String>, AutospielerError> {
+        let candidates = match &self.profile_ids {
+            Some(profile_ids) => profile_ids.clone(),
+            None if teams.is_empty() => {
+                return Err(AutospielerError::markup(
+                    "no teams found, set DAUERZUSAGE_ID to sync specific teams",
+                ))
+            }
+            None => teams.iter().map(|team| team.profile_id.clone()).collect(),
+        };
+
+        Ok(candidates
+            .into_iter()
+            .filter(|profile_id| {
+                let team = teams.iter().find(|team| team.profile_id == *profile_id);
+                (self.include.is_empty() || Self::matches(&self.include, profile_id, team))
+                    && !Self::matches(&self.exclude, profile_id, team)
+            })
+            .collect())
+    }
+}
+
+/// Whether `document` is the "Team auswählen" page.
+pub fn is_team_selection(document: &scraper::Html) -> bool {
+    let title_selector = scraper::Selector::parse("title").unwrap();
+    document
+        .select(&title_selector)
+        .next()
+        .is_some_and(|title| {
+            let title = title.inner_html();
+            title == "Team auswählen" || title == "Select team"
+        })
+}
+
+/// Whether `document` is the login page, which Spielerplus shows instead of every other
+/// page once the session expired.
+pub fn is_login_page(document: &scraper::Html) -> bool {
+    let title_selector = scraper::Selector::parse("title").unwrap();
+    let login_form_selector = scraper::Selector::parse("form#login-form").unwrap();
+    document.select(&login_form_selector).next().is_some()
+        || document
+            .select(&title_selector)
+            .next()
+            .is_some_and(|title| title.inner_html() == "Einloggen")
+}
+
+/// Reads the teams from the "Team auswählen" page.
+pub fn parse_teams(document: &scraper::Html) -> Result<Vec<Team>, AutospielerError> {
+    let select_team_selector = scraper::Selector::parse(".select-team-item").unwrap();
+    let team_meta_selector = scraper::Selector::parse(".select-team-item-meta").unwrap();
+    let team_name_selector = scraper::Selector::parse("h4").unwrap();
+    let team_link_selector = scraper::Selector::parse("a").unwrap();
+
+    let mut teams = Vec::new();
+    for team in document.select(&select_team_selector) {
+        let meta = team
+            .select(&team_meta_selector)
+            .next()
+            .ok_or_else(|| AutospielerError::markup("no .select-team-item-meta found in team"))?;
+        let name_element = meta
+            .select(&team_name_selector)
+            .next()
+            .ok_or_else(|| AutospielerError::markup("no team name found in team"))?;
+
+        let profile_id = team
+            .select(&team_link_selector)
+            .next()
+            .ok_or_else(|| AutospielerError::markup("no team link found in team"))?
+            .value()
+            .attr("href")
+            .ok_or_else(|| AutospielerError::markup("no href attribute found in team link"))?
+            .split_once("=")
+            .ok_or_else(|| AutospielerError::markup("no id found in team link href attribute"))?
+            .1;
+
+        // everything next to the name, e.g. `<small>Trainer</small>`
+        let role = meta
+            .children()
+            .filter(|child| child.id() != name_element.id())
+            .filter_map(scraper::ElementRef::wrap)
+            .map(|element| element.text().collect::<String>())
+            .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .find(|text| !text.is_empty());
+
+        teams.push(Team {
+            profile_id: profile_id.trim().to_owned(),
+            name: name_element.inner_html().trim().to_owned(),
+            role,
+        });
+    }
+
+    Ok(teams)
+}
+
+/// Switches to the team profile `profile_id`, which answers and lists events from then on.
+/// Fails with [`AutospielerError::Authentication`] if the session expired.
+pub fn switch_user(client: &Client, profile_id: &str) -> Result<(), AutospielerError> {
+    let res = client
+        .get(format!(
+            "https://www.spielerplus.de/site/switch-user?id={profile_id}"
+        ))
+        .send_with_retry()?;
+    log::info!(
+        "/site/switch-user response: {:?} {}",
+        res.version(),
+        res.status()
+    );
+    if !res.status().is_success() {
+        return Err(AutospielerError::Spielerplus(format!(
+            "/site/switch-user response status is '{}'",
+            res.status()
+        )));
+    }
+
+    if is_login_page(&scraper::Html::parse_document(&res.text()?)) {
+        return Err(AutospielerError::Authentication(
+            "the Spielerplus session expired".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Fetches the teams of the logged in account.
+pub fn list_teams(client: &Client) -> Result<Vec<Team>, AutospielerError> {
+    let res = client.get(SELECT_TEAM_URL).send_with_retry()?;
+    log::info!(
+        "/site/select-team response: {:?} {}",
+        res.version(),
+        res.status()
+    );
+
+    let document = scraper::Html::parse_document(&res.text()?);
+    if !is_team_selection(&document) {
+        return Err(AutospielerError::markup(
+            "/site/select-team is not the 'Team auswählen' or 'Select team' page",
+        ));
+    }
+
+    parse_teams(&document)
+}
+
+/// Reads a date like `12.03.`, `12.03.24` or `12.03.2024`. Without a year, it's the last
+/// such date on or before `event_start`, as deadlines are before the event.
+fn parse_deadline_date(word: &str, event_start: NaiveDateTime) -> Option<NaiveDate> {
+    let parts: Vec<&str> = word.trim_end_matches('.').split('.').collect();
+    let (day, month) = (parts.first()?.parse().ok()?, parts.get(1)?.parse().ok()?);
+    match parts.get(2) {
+        Some(year) if year.len() == 2 => {
+            NaiveDate::from_ymd_opt(2000 + year.parse::<i32>().ok()?, month, day)
+        }
+        Some(year) => NaiveDate::from_ymd_opt(year.parse().ok()?, month, day),
+        None => {
+            let year = event_start.year();
+            let date = NaiveDate::from_ymd_opt(year, month, day)?;
+            if date > event_start.date() {
+                NaiveDate::from_ymd_opt(year - 1, month, day)
+            } else {
+                Some(date)
+            }
+        }
+    }
+}
+
+/// Reads the participation deadline of an event from the text Spielerplus shows for it,
+/// either a point in time like "Zu-/Absage bis 12.03. 18:00 Uhr" or a time before the
+/// event like "bis 2 Stunden vor Beginn". A date without a time counts from midnight.
+pub fn parse_deadline(text: &str, event_start: NaiveDateTime) -> Option<NaiveDateTime> {
+    let words: Vec<&str> = text
+        .split(|char: char| char.is_whitespace() || char == ',')
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    for (index, word) in words.iter().enumerate() {
+        if word.contains('.') {
+            if let Some(date) = parse_deadline_date(word, event_start) {
+                let time = words
+                    .get(index + 1)
+                    .and_then(|word| NaiveTime::parse_from_str(word, "%H:%M").ok())
+                    .unwrap_or(NaiveTime::MIN);
+                return Some(date.and_time(time));
+            }
+        }
+
+        let Ok(amount) = word.parse::<i64>() else {
+            continue;
+        };
+        let Some(unit) = words.get(index + 1).map(|unit| unit.to_lowercase()) else {
+            continue;
+        };
+        let before = if unit.starts_with("min") {
+            Duration::minutes(amount)
+        } else if unit.starts_with("stunde") || unit.starts_with("std") || unit.starts_with("hour")
+        {
+            Duration::hours(amount)
+        } else if unit.starts_with("tag") || unit.starts_with("day") {
+            Duration::days(amount)
+        } else {
+            continue;
+        };
+        return Some(event_start - before);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// The deadline of an event in the event list, read like a sync does.
+    fn deadline(html: &str, event_start: NaiveDateTime) -> Option<NaiveDateTime> {
+        let fragment = scraper::Html::parse_fragment(html);
+        let selector = scraper::Selector::parse("[class*=\"deadline\"]").unwrap();
+        fragment
+            .select(&selector)
+            .next()
+            .and_then(|element| parse_deadline(&element.text().collect::<String>(), event_start))
+    }
+
+    #[test]
+    fn reads_deadlines_at_a_point_in_time() {
+        let start = at("2024-03-14 19:30");
+        let fixtures = [
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 12.03. 18:00 Uhr</div>"#,
+                "2024-03-12 18:00",
+            ),
+            (
+                r#"<span class="deadline-info">Zu-/Absage bis <b>12.03.24</b>, 18:00 Uhr</span>"#,
+                "2024-03-12 18:00",
+            ),
+            (
+                r#"<div class="event-deadline">
+                    <i class="fa fa-clock-o"></i> Zu-/Absage bis 12.03.2024 18:00
+                </div>"#,
+                "2024-03-12 18:00",
+            ),
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 13.03.2024</div>"#,
+                "2024-03-13 00:00",
+            ),
+        ];
+
+        for (html, expected) in fixtures {
+            assert_eq!(deadline(html, start), Some(at(expected)), "{html}");
+        }
+    }
+
+    #[test]
+    fn takes_a_deadline_without_a_year_from_before_the_event() {
+        let html = r#"<div class="participation-deadline">Zu-/Absage bis 30.12. 12:00 Uhr</div>"#;
+
+        assert_eq!(
+            deadline(html, at("2025-01-03 19:30")),
+            Some(at("2024-12-30 12:00"))
+        );
+    }
+
+    #[test]
+    fn reads_deadlines_before_the_event() {
+        let start = at("2024-03-14 19:30");
+        let fixtures = [
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 2 Stunden vor Beginn</div>"#,
+                "2024-03-14 17:30",
+            ),
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 1 Tag vor Beginn</div>"#,
+                "2024-03-13 19:30",
+            ),
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 3 Tage vor Beginn</div>"#,
+                "2024-03-11 19:30",
+            ),
+            (
+                r#"<div class="participation-deadline">Zu-/Absage bis 30 Minuten vor Beginn</div>"#,
+                "2024-03-14 19:00",
+            ),
+            (
+                r#"<div class="participation-deadline">Reply until 4 hours before the start</div>"#,
+                "2024-03-14 15:30",
+            ),
+        ];
+
+        for (html, expected) in fixtures {
+            assert_eq!(deadline(html, start), Some(at(expected)), "{html}");
+        }
+    }
+
+    #[test]
+    fn reads_events_without_a_deadline() {
+        let start = at("2024-03-14 19:30");
+
+        assert_eq!(
+            deadline(r#"<div class="event-time">19:30 - 21:00</div>"#, start),
+            None
+        );
+        assert_eq!(
+            deadline(
+                r#"<div class="participation-deadline">Keine Zu-/Absagefrist</div>"#,
+                start
+            ),
+            None
+        );
+        assert_eq!(parse_deadline("", start), None);
+    }
+
+    #[test]
+    fn recognizes_the_login_page() {
+        let login_page = scraper::Html::parse_document(
+            r#"<html><head><title>Einloggen</title></head><body>
+                <form id="login-form" action="/site/login" method="post"></form>
+            </body></html>"#,
+        );
+        assert!(is_login_page(&login_page));
+
+        let events_page = scraper::Html::parse_document(
+            r#"<html><head><title>Termine</title></head><body>
+                <div class="event"></div>
+            </body></html>"#,
+        );
+        assert!(!is_login_page(&events_page));
+    }
+}
@@ -0,0 +1,112 @@
+//! Local stand-in for the HTTP APIs Autospieler talks to, used by the tests.
+
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+use tiny_http::{Header, Response, Server};
+
+/// A request the stub server received.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query, e.g. `/$batch`.
+    pub url: String,
+    pub body: String,
+}
+
+pub struct Reply {
+    pub status: u16,
+    /// `{base}` is replaced with the URL of the stub server, e.g. for next links.
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Reply {
+    pub fn json(status: u16, body: &str) -> Self {
+        Reply {
+            status,
+            body: body.to_owned(),
+            headers: vec![("Content-Type".to_owned(), "application/json".to_owned())],
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+/// Answers every request with the reply of `handler` until it's dropped.
+pub struct StubServer {
+    base_url: String,
+    server: Arc<Server>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StubServer {
+    pub fn start<F>(mut handler: F) -> Self
+    where
+        F: FnMut(&RecordedRequest) -> Reply + Send + 'static,
+    {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let base_url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let thread = {
+            let server = Arc::clone(&server);
+            let requests = Arc::clone(&requests);
+            let base_url = base_url.clone();
+            thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let mut body = String::new();
+                    request.as_reader().read_to_string(&mut body).unwrap();
+                    let recorded = RecordedRequest {
+                        method: request.method().to_string(),
+                        url: request.url().to_owned(),
+                        body,
+                    };
+
+                    let reply = handler(&recorded);
+                    requests.lock().unwrap().push(recorded);
+
+                    let mut response =
+                        Response::from_string(reply.body.replace("{base}", &base_url))
+                            .with_status_code(reply.status);
+                    for (name, value) in reply.headers {
+                        response.add_header(
+                            Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap(),
+                        );
+                    }
+                    let _ = request.respond(response);
+                }
+            })
+        };
+
+        StubServer {
+            base_url,
+            server,
+            requests,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for StubServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}